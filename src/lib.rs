@@ -5,383 +5,8004 @@
 
 use nom::{
     branch::alt,
-    bytes::complete::tag,
-    character::complete::{digit0, multispace0, multispace1},
-    combinator::opt,
-    error::ErrorKind,
-    sequence::{preceded, separated_pair, tuple},
-    AsChar, IResult, InputTakeAtPosition,
+    bytes::complete::{tag, take_till},
+    character::complete::{
+        char, digit1, hex_digit1, line_ending, multispace1, not_line_ending, space0,
+    },
+    combinator::{eof, map, opt, peek, recognize, value, verify},
+    error::{context, ContextError, Error, ErrorKind, FromExternalError, ParseError},
+    multi::many0_count,
+    sequence::{preceded, separated_pair, terminated, tuple},
+    Err, IResult, InputTakeAtPosition,
 };
 
-use core::str::FromStr;
+use core::{fmt, num::ParseIntError, ops::Range, str::FromStr};
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+#[cfg(feature = "alloc")]
+use alloc::{
+    borrow::Cow,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
 
 /// A struct to hold the active-image configuration i.e. a fitimage
 /// that's already been successfully booted in the past.
-#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ActiveConf<'a> {
     active_config: ConfigKeys,
+    #[cfg_attr(feature = "serde", serde(with = "serde_label", borrow))]
+    image_name: ImageLabel<'a>,
+    #[cfg_attr(feature = "serde", serde(borrow, default))]
+    image_dir: Option<&'a str>,
+    image_version: u32,
+    image_semver: Option<ImageVersion>,
+    image_hash: Option<[u8; 32]>,
+    channel: Option<Channel>,
+    min_hwrev: Option<u32>,
+}
+
+/// A struct to hold the `[board]` configuration i.e. the hardware a config file was written
+/// for, so a bootloader can check it before acting on the rest of the file.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BoardConf<'a> {
+    board_config: ConfigKeys,
+    model: &'a str,
+    hwrev: u32,
+}
+
+/// A struct to hold the recovery-image configuration i.e. a fallback fitimage to boot when
+/// neither the active nor the passive image can be.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecoveryConf<'a> {
+    recovery_config: ConfigKeys,
+    #[cfg_attr(feature = "serde", serde(with = "serde_label", borrow))]
     image_name: ImageLabel<'a>,
+    #[cfg_attr(feature = "serde", serde(borrow, default))]
+    image_dir: Option<&'a str>,
     image_version: u32,
+    image_semver: Option<ImageVersion>,
+    image_hash: Option<[u8; 32]>,
+    min_hwrev: Option<u32>,
 }
 
 /// A struct to hold the passive-image configuration i.e. a newly downloaded fitimage
 /// that's been marked for `update` on the next reboot.
-#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PassiveConf<'a> {
     passive_config: ConfigKeys,
     ready_for_update_flag: bool,
+    #[cfg_attr(feature = "serde", serde(with = "serde_label::option", borrow))]
+    image_name: Option<ImageLabel<'a>>,
+    #[cfg_attr(feature = "serde", serde(borrow, default))]
+    image_dir: Option<&'a str>,
+    image_version: Option<u32>,
+    image_semver: Option<ImageVersion>,
+    image_hash: Option<[u8; 32]>,
+    channel: Option<Channel>,
+    min_hwrev: Option<u32>,
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    update_status: Option<UpdateStatus<'a>>,
+    boot_attempts: Option<u32>,
+    test_window_secs: Option<u32>,
+    dry_run: Option<bool>,
+    staged_at: Option<u64>,
+    retry_backoff: Option<RetryBackoff>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    unknown_fields: UnknownFields<'a>,
+}
+
+/// The `key=value` lines of a `[passive]` component whose keys this crate doesn't know (ex:
+/// fields added by a newer tool), as returned by [`PassiveConf::unknown_fields`]. They're
+/// kept as written so that `Display` writes them back out, after the known fields. Two sets
+/// are equal if they hold the same pairs in the same order. They aren't serialized with the
+/// `serde` feature.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UnknownFields<'a> {
+    // The text of the section's fields, only the pairs with unknown keys are yielded
+    text: &'a str,
+}
+
+impl<'a> UnknownFields<'a> {
+    /// No fields, as in a [`PassiveConf`] that wasn't parsed.
+    const EMPTY: Self = UnknownFields { text: "" };
+
+    /// Iterates over the raw `(key, value)` pairs, as [`section_pairs`] sees them, in the
+    /// order they appear.
+    pub fn iter(&self) -> impl Iterator<Item = (&'a str, &'a str)> {
+        section_pairs(self.text).filter(|(key, _)| !PASSIVE_KEYS.contains(key))
+    }
+
+    /// Returns how many fields there are.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// Returns `true` if there are no fields.
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+}
+
+impl PartialEq for UnknownFields<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl Eq for UnknownFields<'_> {}
+
+/// Builds a [`PassiveConf`] programmatically, ex: to serialize a freshly staged update
+/// without round-tripping through text. `ready_for_update_flag` must be set before
+/// [`build`](PassiveConfBuilder::build) succeeds; every other field is optional.
+#[derive(Debug, Default)]
+pub struct PassiveConfBuilder<'a> {
+    ready_for_update_flag: Option<bool>,
     image_name: Option<ImageLabel<'a>>,
+    image_dir: Option<&'a str>,
     image_version: Option<u32>,
-    update_status: Option<UpdateStatus>,
+    image_hash: Option<[u8; 32]>,
+    channel: Option<Channel>,
+    min_hwrev: Option<u32>,
+    update_status: Option<UpdateStatus<'a>>,
+    boot_attempts: Option<u32>,
+    test_window_secs: Option<u32>,
+    dry_run: Option<bool>,
+    staged_at: Option<u64>,
+    retry_backoff: Option<RetryBackoff>,
+}
+
+/// A struct to hold the optional, board-wide `[settings]` configuration.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SettingsConf {
+    settings_config: ConfigKeys,
+    watchdog_timeout: Option<u32>,
+    auto_rollback: Option<bool>,
+}
+
+/// The `[signature]` section a signed config ends with, as returned by [`parse_signed`]. The
+/// crate doesn't check the signature; it only hands the fields to the caller's verifier.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "SignatureFields<'a>"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignatureBlock<'a> {
+    alg: &'a str,
+    sig: &'a str,
+}
+
+/// A parsed configuration file, holding both the active and passive components and,
+/// if present, the global settings.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config<'a> {
+    /// The version of the format the file is written in, from its `version=` line, or
+    /// [`DEFAULT_FORMAT_VERSION`] without one.
+    pub format_version: u32,
+    /// The leading `[board]` configuration, or `None` if the section is absent.
+    #[cfg_attr(feature = "serde", serde(borrow, default))]
+    pub board: Option<BoardConf<'a>>,
+    /// The active-image configuration.
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    pub active: ActiveConf<'a>,
+    /// The passive-image configuration.
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    pub passive: PassiveConf<'a>,
+    /// The `[recovery]` configuration, or `None` if the section is absent.
+    #[cfg_attr(feature = "serde", serde(borrow, default))]
+    pub recovery: Option<RecoveryConf<'a>>,
+    /// The `[settings]` configuration, or `None` if the section is absent.
+    pub settings: Option<SettingsConf>,
+    /// Any `[passive]` components after the first, in the order they appear.
+    #[cfg(feature = "multi-slot")]
+    #[cfg_attr(feature = "serde", serde(borrow, default))]
+    pub extra_passive: heapless::Vec<PassiveConf<'a>, { MAX_PASSIVE_SLOTS - 1 }>,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// The most `[passive]` components a [`Config`] can hold.
+#[cfg(feature = "multi-slot")]
+pub const MAX_PASSIVE_SLOTS: usize = 4;
+
+/// The scalar fields of a [`Config`], as returned by [`parse_scalars`]. Holds no
+/// references into the input, so the source buffer can be dropped straight away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConfigScalars {
+    /// The active image's version.
+    pub active_version: u32,
+    /// The passive component's `ready_for_update_flag`.
+    pub passive_flag: bool,
+    /// The passive image's version, if any.
+    pub passive_version: Option<u32>,
+    /// The passive image's update status, if any.
+    pub passive_status: Option<UpdateStatus<'static>>,
+}
+
+/// What [`parse_config_reported`] got through, ex: to check that a config partition wasn't
+/// truncated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ParseReport {
+    /// How many sections were recognized.
+    pub sections: usize,
+    /// How many bytes of the input were consumed, i.e. `input.len() - remainder.len()`.
+    pub bytes_consumed: usize,
+}
+
+/// Which fields differ between two configs, as returned by [`diff`]. Each flag is `true`
+/// if the field changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConfigDiff {
+    /// The `[board]` component.
+    pub board: bool,
+    /// The active image's name.
+    pub active_name: bool,
+    /// The active image's version, including its semantic form.
+    pub active_version: bool,
+    /// The active image's hash.
+    pub active_hash: bool,
+    /// The active image's channel.
+    pub active_channel: bool,
+    /// The active image's minimum board revision.
+    pub active_min_hwrev: bool,
+    /// The passive component's `ready_for_update_flag`.
+    pub passive_flag: bool,
+    /// The passive image's name.
+    pub passive_name: bool,
+    /// The passive image's version, including its semantic form.
+    pub passive_version: bool,
+    /// The passive image's hash.
+    pub passive_hash: bool,
+    /// The passive image's channel.
+    pub passive_channel: bool,
+    /// The passive image's minimum board revision.
+    pub passive_min_hwrev: bool,
+    /// The passive image's update status.
+    pub passive_status: bool,
+    /// The passive image's boot attempt count.
+    pub boot_attempts: bool,
+    /// The passive image's testing window.
+    pub test_window_secs: bool,
+    /// The passive image's `dry_run` marker.
+    pub dry_run: bool,
+    /// When the passive image was staged.
+    pub staged_at: bool,
+    /// The passive image's boot retry schedule.
+    pub retry_backoff: bool,
+    /// The `[recovery]` component.
+    pub recovery: bool,
+    /// The `[settings]` component.
+    pub settings: bool,
+}
+
+impl ConfigDiff {
+    /// Returns `true` if no field changed.
+    pub fn is_empty(&self) -> bool {
+        *self == ConfigDiff::default()
+    }
+}
+
+/// A [`Config`] that owns its strings, so it can outlive the buffer it was parsed from.
+/// Use [`as_config`](OwnedConfig::as_config) to get a borrowed view with the usual accessors.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnedConfig {
+    board_model: Option<String>,
+    active_name: OwnedPath,
+    passive_name: Option<OwnedPath>,
+    recovery_name: Option<OwnedPath>,
+    #[cfg(feature = "multi-slot")]
+    extra_passive_names: heapless::Vec<Option<OwnedPath>, { MAX_PASSIVE_SLOTS - 1 }>,
+    // The text of each passive slot's `UpdateStatus::Unknown`, if it has one.
+    passive_status: Option<String>,
+    #[cfg(feature = "multi-slot")]
+    extra_passive_statuses: heapless::Vec<Option<String>, { MAX_PASSIVE_SLOTS - 1 }>,
+    // Each passive slot's unknown fields, as `key=value` lines.
+    passive_unknown: String,
+    #[cfg(feature = "multi-slot")]
+    extra_passive_unknown: heapless::Vec<String, { MAX_PASSIVE_SLOTS - 1 }>,
+    // `config` with its image names, unknown statuses and unknown fields blanked out; they
+    // live in the fields above.
+    config: Config<'static>,
+}
+
+/// An owned image directory, name and extension.
+#[cfg(feature = "alloc")]
+type OwnedPath = (Option<String>, String, String);
+
+/// The names of the section headers, without their brackets, for configs that rename the
+/// sections (ex: `[slot_a]`/`[slot_b]`). See [`parse_config_with`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SectionNames<'n> {
+    /// The name of the board section.
+    pub board: &'n str,
+    /// The name of the active section.
+    pub active: &'n str,
+    /// The name of the passive section.
+    pub passive: &'n str,
+    /// The name of the recovery section.
+    pub recovery: &'n str,
+    /// The name of the settings section.
+    pub settings: &'n str,
+}
+
+impl SectionNames<'static> {
+    /// The standard `[board]`, `[active]`, `[passive]`, `[recovery]` and `[settings]` names.
+    pub const DEFAULT: Self = SectionNames {
+        board: "board",
+        active: "active",
+        passive: "passive",
+        recovery: "recovery",
+        settings: "settings",
+    };
+}
+
+impl Default for SectionNames<'static> {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// How strict [`parse_with_options`] is. The default options are as strict as [`parse`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseOptions<'x> {
+    /// What to do with a field that appears more than once in a section.
+    pub duplicate_fields: DuplicateFields,
+    /// Read an `update_status` this crate doesn't know as [`UpdateStatus::Unknown`] rather
+    /// than failing, ex: for a bootloader reading a file written by a newer updater.
+    pub lenient_status: bool,
+    /// The extensions an `image_name` may end with (ex: `&[".itb", ".fit"]`), see
+    /// [`image_path_with_ext`]. [`parse`] only accepts `.itb`.
+    pub image_extensions: &'x [&'x str],
+}
+
+impl ParseOptions<'static> {
+    /// The options [`parse`] uses.
+    pub const DEFAULT: Self = ParseOptions {
+        duplicate_fields: DuplicateFields::Reject,
+        lenient_status: false,
+        image_extensions: &[".itb"],
+    };
+}
+
+impl Default for ParseOptions<'static> {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// What to do with a field that appears more than once in a section. See
+/// [`parse_with_duplicates`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicateFields {
+    /// Fail with [`ConfigError::DuplicateField`], as [`parse`] does.
+    #[default]
+    Reject,
+    /// Keep the first value and skip the lines that repeat it.
+    KeepFirst,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum ConfigKeys {
+    Board,
     Active,
     Passive,
+    Recovery,
+    Settings,
 }
 
-#[derive(Debug, PartialEq, Eq)]
-enum UpdateStatus {
+/// The state of an update staged in the passive slot. Statuses order by progress:
+/// `Unknown < Failed < Updating < Testing < Success < Committed`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum UpdateStatus<'a> {
+    /// The passive image is being written.
     Updating,
+    /// The passive image has been booted and is under test.
     Testing,
+    /// The passive image passed its test boot.
     Success,
+    /// The passive image has been permanently accepted after passing its test boot.
+    Committed,
+    /// The passive image failed its test boot.
+    Failed,
+    /// A status this crate doesn't know, as it appeared in the input. Only produced when
+    /// [`ParseOptions::lenient_status`] is set.
+    Unknown(&'a str),
 }
 
-/// A label consists of a `filename` and a file extension (ex: `.itb`)
-pub type ImageLabel<'a> = (&'a str, &'a str);
+/// The release channel an image was built for, from its `channel` field.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Channel {
+    /// Released images.
+    Stable,
+    /// Release candidates.
+    Beta,
+    /// Development builds.
+    Dev,
+}
 
-impl From<&str> for ConfigKeys {
-    fn from(i: &str) -> Self {
-        match i {
-            "[active]" => ConfigKeys::Active,
-            "[passive]" => ConfigKeys::Passive,
-            _ => unimplemented!("no other image types supported"),
+/// The most delays a `retry_backoff_secs` schedule holds.
+pub const MAX_BACKOFF_STEPS: usize = 8;
+
+/// The delays, in seconds, between boot retries from a passive section's
+/// `retry_backoff_secs` field (ex: `1,2,4,8`). Holds up to [`MAX_BACKOFF_STEPS`] of them.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryBackoff {
+    secs: [u32; MAX_BACKOFF_STEPS],
+    len: usize,
+}
+
+impl RetryBackoff {
+    /// Creates a schedule from the given delays, or returns `None` if there are more than
+    /// [`MAX_BACKOFF_STEPS`] of them.
+    pub fn new(delays: &[u32]) -> Option<Self> {
+        let mut secs = [0; MAX_BACKOFF_STEPS];
+        secs.get_mut(..delays.len())?.copy_from_slice(delays);
+        Some(RetryBackoff {
+            secs,
+            len: delays.len(),
+        })
+    }
+
+    /// Returns the delays, in seconds, in the order they apply.
+    pub fn as_slice(&self) -> &[u32] {
+        &self.secs[..self.len.min(MAX_BACKOFF_STEPS)]
+    }
+}
+
+/// The slot to boot, as decided by [`Config::boot_target`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootTarget {
+    /// Boot the image in the active slot.
+    Active,
+    /// Boot the image in the passive slot.
+    Passive,
+}
+
+/// A semantic image version i.e. `ver_<major>.<minor>.<patch>`. Versions compare
+/// by `major`, then `minor`, then `patch`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ImageVersion {
+    /// The major version component.
+    pub major: u32,
+    /// The minor version component.
+    pub minor: u32,
+    /// The patch version component.
+    pub patch: u32,
+}
+
+impl From<u32> for ImageVersion {
+    /// A single-integer version `ver_<n>` is treated as `<n>.0.0`.
+    fn from(major: u32) -> Self {
+        ImageVersion {
+            major,
+            minor: 0,
+            patch: 0,
         }
     }
 }
 
-impl From<&str> for UpdateStatus {
-    fn from(i: &str) -> Self {
-        match i {
-            "updating" => UpdateStatus::Updating,
-            "testing" => UpdateStatus::Testing,
-            "success" => UpdateStatus::Success,
-            _ => unreachable!("invalid image state was set"),
+impl<'a> ActiveConf<'a> {
+    /// Creates an active-image configuration with an integer `image_version`.
+    pub fn new(image_name: ImageLabel<'a>, image_version: u32) -> Self {
+        ActiveConf {
+            active_config: ConfigKeys::Active,
+            image_name,
+            image_dir: None,
+            image_version,
+            image_semver: None,
+            image_hash: None,
+            channel: None,
+            min_hwrev: None,
+        }
+    }
+
+    /// Returns the label of the active image.
+    pub fn image_name(&self) -> ImageLabel<'a> {
+        self.image_name
+    }
+
+    /// Returns the directory of the active image, separator included (ex: `/boot/`), if its
+    /// `image_name` is a path. See [`image_path_with_ext`].
+    pub fn image_dir(&self) -> Option<&'a str> {
+        self.image_dir
+    }
+
+    /// Returns the format of the active image.
+    pub fn image_format(&self) -> ImageFormat<'a> {
+        self.image_name.1.into()
+    }
+
+    /// Returns the version of the active image. For a semantic version, this is
+    /// the `major` component.
+    pub fn image_version(&self) -> u32 {
+        self.image_version
+    }
+
+    /// Returns the version of the active image as an [`ImageVersion`].
+    pub fn image_semver(&self) -> ImageVersion {
+        self.image_semver
+            .unwrap_or_else(|| self.image_version.into())
+    }
+
+    /// Returns the expected SHA-256 of the active image, if one is given.
+    pub fn image_hash(&self) -> Option<[u8; 32]> {
+        self.image_hash
+    }
+
+    /// Returns the release channel of the active image, if one is given.
+    pub fn channel(&self) -> Option<Channel> {
+        self.channel
+    }
+
+    /// Returns the lowest board revision the active image supports, if one is given.
+    pub fn min_hwrev(&self) -> Option<u32> {
+        self.min_hwrev
+    }
+
+    /// Returns `true` if the active image may boot on a board of revision `hwrev`, i.e. it
+    /// has no `min_hwrev` or one no greater than `hwrev`.
+    pub fn allows_hwrev(&self, hwrev: u32) -> bool {
+        self.min_hwrev.is_none_or(|min| min <= hwrev)
+    }
+}
+
+impl<'a> BoardConf<'a> {
+    /// Returns the board model the config was written for.
+    pub fn model(&self) -> &'a str {
+        self.model
+    }
+
+    /// Returns the hardware revision of the board the config was written for.
+    pub fn hwrev(&self) -> u32 {
+        self.hwrev
+    }
+}
+
+impl<'a> SignatureBlock<'a> {
+    /// Returns the signature algorithm (ex: `"ed25519"`).
+    pub fn alg(&self) -> &'a str {
+        self.alg
+    }
+
+    /// Returns the signature as written, in standard padded base64.
+    pub fn sig_base64(&self) -> &'a str {
+        self.sig
+    }
+
+    /// Returns the decoded signature bytes, or [`ConfigError::InvalidSignature`] if the
+    /// signature isn't padded base64.
+    #[cfg(feature = "alloc")]
+    pub fn sig(&self) -> Result<Vec<u8>, ConfigError<'a>> {
+        let invalid = || ConfigError::InvalidSignature(self.sig);
+        if !is_base64(self.sig) {
+            return Err(invalid());
+        }
+        let mut sig = Vec::with_capacity(self.sig.len() / 4 * 3);
+        for chunk in self.sig.as_bytes().chunks(4) {
+            let digits = chunk.iter().take_while(|b| **b != b'=');
+            let len = digits.clone().count();
+            let bits = digits
+                .map(|b| base64_digit(*b))
+                .try_fold(0u32, |bits, digit| Some((bits << 6) | u32::from(digit?)))
+                .ok_or_else(invalid)?;
+            let bytes = (bits << (6 * (4 - len))).to_be_bytes();
+            sig.extend_from_slice(bytes.get(1..len).ok_or_else(invalid)?);
+        }
+        Ok(sig)
+    }
+}
+
+impl<'a> RecoveryConf<'a> {
+    /// Returns the label of the recovery image.
+    pub fn image_name(&self) -> ImageLabel<'a> {
+        self.image_name
+    }
+
+    /// Returns the directory of the recovery image, separator included (ex: `/boot/`), if its
+    /// `image_name` is a path. See [`image_path_with_ext`].
+    pub fn image_dir(&self) -> Option<&'a str> {
+        self.image_dir
+    }
+
+    /// Returns the version of the recovery image. For a semantic version, this is
+    /// the `major` component.
+    pub fn image_version(&self) -> u32 {
+        self.image_version
+    }
+
+    /// Returns the version of the recovery image as an [`ImageVersion`].
+    pub fn image_semver(&self) -> ImageVersion {
+        self.image_semver
+            .unwrap_or_else(|| self.image_version.into())
+    }
+
+    /// Returns the expected SHA-256 of the recovery image, if one is given.
+    pub fn image_hash(&self) -> Option<[u8; 32]> {
+        self.image_hash
+    }
+
+    /// Returns the lowest board revision the recovery image supports, if one is given.
+    pub fn min_hwrev(&self) -> Option<u32> {
+        self.min_hwrev
+    }
+
+    /// Returns `true` if the recovery image may boot on a board of revision `hwrev`, i.e. it
+    /// has no `min_hwrev` or one no greater than `hwrev`.
+    pub fn allows_hwrev(&self, hwrev: u32) -> bool {
+        self.min_hwrev.is_none_or(|min| min <= hwrev)
+    }
+}
+
+impl<'a> PassiveConf<'a> {
+    /// Returns `true` if the passive image is marked for `update` on the next reboot.
+    pub fn ready_for_update_flag(&self) -> bool {
+        self.ready_for_update_flag
+    }
+
+    /// Sets or clears the `update` request for the passive image, ex: before writing the
+    /// config back with [`Config`]'s `Display` impl.
+    pub fn set_ready_for_update(&mut self, val: bool) {
+        self.ready_for_update_flag = val;
+    }
+
+    /// Returns the label of the passive image, if one is staged.
+    pub fn image_name(&self) -> Option<ImageLabel<'a>> {
+        self.image_name
+    }
+
+    /// Returns the directory of the passive image, separator included (ex: `/boot/`), if its
+    /// `image_name` is a path. See [`image_path_with_ext`].
+    pub fn image_dir(&self) -> Option<&'a str> {
+        self.image_dir
+    }
+
+    /// Returns the format of the passive image, if one is staged.
+    pub fn image_format(&self) -> Option<ImageFormat<'a>> {
+        self.image_name.map(|(_, ext)| ext.into())
+    }
+
+    /// Returns the version of the passive image, if one is staged. For a semantic
+    /// version, this is the `major` component.
+    pub fn image_version(&self) -> Option<u32> {
+        self.image_version
+    }
+
+    /// Returns the name and version of the staged image, or `None` if the slot isn't ready
+    /// for update. A slot whose `ready_for_update_flag` is `true` must have both, even though
+    /// the grammar makes them optional, so this fails with [`ConfigError::MissingField`]
+    /// naming the absent field for a ready slot that lacks either.
+    pub fn staged_image(&self) -> Result<Option<(ImageLabel<'a>, u32)>, ConfigError<'a>> {
+        if !self.ready_for_update_flag {
+            return Ok(None);
+        }
+        let image_name = self
+            .image_name
+            .ok_or(ConfigError::MissingField(IMAGE_NAME_KEY))?;
+        let image_version = self
+            .image_version
+            .ok_or(ConfigError::MissingField(IMAGE_VERSION_KEY))?;
+        Ok(Some((image_name, image_version)))
+    }
+
+    /// Returns the version of the passive image as an [`ImageVersion`], if one is staged.
+    pub fn image_semver(&self) -> Option<ImageVersion> {
+        self.image_semver
+            .or_else(|| self.image_version.map(ImageVersion::from))
+    }
+
+    /// Returns the expected SHA-256 of the passive image, if one is given.
+    pub fn image_hash(&self) -> Option<[u8; 32]> {
+        self.image_hash
+    }
+
+    /// Returns the update status of the passive image, if one is staged.
+    pub fn update_status(&self) -> Option<UpdateStatus<'a>> {
+        self.update_status
+    }
+
+    /// Returns the release channel of the passive image, if one is given.
+    pub fn channel(&self) -> Option<Channel> {
+        self.channel
+    }
+
+    /// Returns the lowest board revision the passive image supports, if one is given.
+    pub fn min_hwrev(&self) -> Option<u32> {
+        self.min_hwrev
+    }
+
+    /// Returns `true` if the passive image may boot on a board of revision `hwrev`, i.e. it
+    /// has no `min_hwrev` or one no greater than `hwrev`.
+    pub fn allows_hwrev(&self, hwrev: u32) -> bool {
+        self.min_hwrev.is_none_or(|min| min <= hwrev)
+    }
+
+    /// Returns `true` if nothing is staged in the passive slot, i.e. it isn't ready for
+    /// update and has no image name, version or status, as after an update is committed.
+    pub fn is_empty(&self) -> bool {
+        !self.ready_for_update_flag
+            && self.image_name.is_none()
+            && self.image_version.is_none()
+            && self.update_status.is_none()
+    }
+
+    /// Empties the passive slot, ex: after an update is committed or rolled back: the flag is
+    /// cleared and every optional field is set to `None`, leaving the slot as the parser
+    /// returns a `[passive]` component holding only `ready_for_update_flag=false`.
+    pub fn clear(&mut self) {
+        *self = PassiveConf {
+            passive_config: self.passive_config,
+            ..PassiveConf::empty()
+        };
+    }
+
+    /// An empty `[passive]` component, as left by [`clear`](PassiveConf::clear).
+    const fn empty() -> Self {
+        PassiveConf {
+            passive_config: ConfigKeys::Passive,
+            ready_for_update_flag: false,
+            image_name: None,
+            image_dir: None,
+            image_version: None,
+            image_semver: None,
+            image_hash: None,
+            channel: None,
+            min_hwrev: None,
+            update_status: None,
+            boot_attempts: None,
+            test_window_secs: None,
+            dry_run: None,
+            staged_at: None,
+            retry_backoff: None,
+            unknown_fields: UnknownFields::EMPTY,
+        }
+    }
+
+    /// Returns `true` if an update is under way, i.e. the status is `Updating` or `Testing`.
+    /// A passive slot with no status, or one that has succeeded or failed, is not.
+    pub fn is_update_in_progress(&self) -> bool {
+        matches!(
+            self.update_status,
+            Some(UpdateStatus::Updating | UpdateStatus::Testing)
+        )
+    }
+
+    /// Returns how many times the passive image has been booted, if recorded.
+    pub fn boot_attempts(&self) -> Option<u32> {
+        self.boot_attempts
+    }
+
+    /// Returns how many seconds the passive image has, while under test, to be marked a
+    /// success before it's rolled back, if limited.
+    pub fn test_window_secs(&self) -> Option<u32> {
+        self.test_window_secs
+    }
+
+    /// Returns the passive section's `dry_run` marker, if given.
+    pub fn dry_run(&self) -> Option<bool> {
+        self.dry_run
+    }
+
+    /// Returns `true` if the passive image is a dry run, i.e. it may be booted once but must
+    /// never be committed. A passive section without a `dry_run` marker isn't one.
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run == Some(true)
+    }
+
+    /// Returns when the passive image was staged, in seconds since the Unix epoch, if
+    /// recorded. Ex: a bootloader may refuse to test an image that was staged too long ago.
+    pub fn staged_at(&self) -> Option<u64> {
+        self.staged_at
+    }
+
+    /// Returns the delays, in seconds, to wait between boot retries, if a schedule is given.
+    pub fn retry_backoff(&self) -> Option<&[u32]> {
+        self.retry_backoff.as_ref().map(RetryBackoff::as_slice)
+    }
+
+    /// Returns how many unrecognized `key=value` lines were skipped while parsing. Use
+    /// [`unknown_fields`](PassiveConf::unknown_fields) to get at their contents.
+    pub fn unknown_keys(&self) -> usize {
+        self.unknown_fields.len()
+    }
+
+    /// Returns the unrecognized `key=value` lines that were skipped while parsing.
+    pub fn unknown_fields(&self) -> UnknownFields<'a> {
+        self.unknown_fields
+    }
+
+    /// Returns `true` once the passive image has been booted `limit` or more times without
+    /// succeeding, i.e. the bootloader should give up on it and roll back to the active image.
+    /// A passive section without a `boot_attempts` count never requires a rollback.
+    pub fn is_rollback_required(&self, limit: u32) -> bool {
+        self.boot_attempts.is_some_and(|attempts| attempts >= limit)
+    }
+}
+
+impl SettingsConf {
+    /// Returns the watchdog timeout, if one is configured.
+    pub fn watchdog_timeout(&self) -> Option<u32> {
+        self.watchdog_timeout
+    }
+
+    /// Returns whether a failed update should be rolled back automatically, if configured.
+    pub fn auto_rollback(&self) -> Option<bool> {
+        self.auto_rollback
+    }
+}
+
+/// A check run by [`Config::validate`].
+type ValidationRule = for<'a> fn(&Config<'a>) -> Result<(), ConfigError<'a>>;
+
+/// The rules [`Config::validate`] applies, in order. New rules go here.
+const VALIDATION_RULES: &[ValidationRule] = &[distinct_image_names];
+
+fn distinct_image_names<'a>(config: &Config<'a>) -> Result<(), ConfigError<'a>> {
+    match config.passive.image_name {
+        Some(name)
+            if (config.passive.image_dir, name)
+                == (config.active.image_dir, config.active.image_name) =>
+        {
+            Err(ConfigError::DuplicateImageName(config.active.image_name.0))
+        }
+        _ => Ok(()),
+    }
+}
+
+impl<'a> PassiveConfBuilder<'a> {
+    /// Creates an empty builder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether the passive image is marked for `update` on the next reboot.
+    pub fn ready_for_update(mut self, flag: bool) -> Self {
+        self.ready_for_update_flag = Some(flag);
+        self
+    }
+
+    /// Sets the label of the staged image.
+    pub fn image_name(mut self, image_name: ImageLabel<'a>) -> Self {
+        self.image_name = Some(image_name);
+        self
+    }
+
+    /// Sets the directory of the staged image, separator included (ex: `/boot/`).
+    pub fn image_dir(mut self, image_dir: &'a str) -> Self {
+        self.image_dir = Some(image_dir);
+        self
+    }
+
+    /// Sets the integer version of the staged image.
+    pub fn image_version(mut self, image_version: u32) -> Self {
+        self.image_version = Some(image_version);
+        self
+    }
+
+    /// Sets the expected SHA-256 of the staged image.
+    pub fn image_hash(mut self, image_hash: [u8; 32]) -> Self {
+        self.image_hash = Some(image_hash);
+        self
+    }
+
+    /// Sets the release channel of the staged image.
+    pub fn channel(mut self, channel: Channel) -> Self {
+        self.channel = Some(channel);
+        self
+    }
+
+    /// Sets the lowest board revision the staged image supports.
+    pub fn min_hwrev(mut self, min_hwrev: u32) -> Self {
+        self.min_hwrev = Some(min_hwrev);
+        self
+    }
+
+    /// Sets the update status of the staged image.
+    pub fn update_status(mut self, update_status: UpdateStatus<'a>) -> Self {
+        self.update_status = Some(update_status);
+        self
+    }
+
+    /// Sets how many times the staged image has been booted.
+    pub fn boot_attempts(mut self, boot_attempts: u32) -> Self {
+        self.boot_attempts = Some(boot_attempts);
+        self
+    }
+
+    /// Sets how many seconds the staged image has to pass its test boot.
+    pub fn test_window_secs(mut self, test_window_secs: u32) -> Self {
+        self.test_window_secs = Some(test_window_secs);
+        self
+    }
+
+    /// Marks the staged image as a dry run, or explicitly not one.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = Some(dry_run);
+        self
+    }
+
+    /// Sets when the image was staged, in seconds since the Unix epoch.
+    pub fn staged_at(mut self, staged_at: u64) -> Self {
+        self.staged_at = Some(staged_at);
+        self
+    }
+
+    /// Sets the delays to wait between boot retries.
+    pub fn retry_backoff(mut self, retry_backoff: RetryBackoff) -> Self {
+        self.retry_backoff = Some(retry_backoff);
+        self
+    }
+
+    /// Returns the configured [`PassiveConf`], or [`ConfigError::MissingField`] if
+    /// `ready_for_update_flag` was never set.
+    pub fn build(self) -> Result<PassiveConf<'a>, ConfigError<'a>> {
+        let ready_for_update_flag = self
+            .ready_for_update_flag
+            .ok_or(ConfigError::MissingField(READY_FOR_UPDATE_KEY))?;
+        Ok(PassiveConf {
+            passive_config: ConfigKeys::Passive,
+            ready_for_update_flag,
+            image_name: self.image_name,
+            image_dir: self.image_dir.filter(|_| self.image_name.is_some()),
+            image_version: self.image_version,
+            image_semver: None,
+            image_hash: self.image_hash,
+            channel: self.channel,
+            min_hwrev: self.min_hwrev,
+            update_status: self.update_status,
+            boot_attempts: self.boot_attempts,
+            test_window_secs: self.test_window_secs,
+            dry_run: self.dry_run,
+            staged_at: self.staged_at,
+            retry_backoff: self.retry_backoff,
+            unknown_fields: UnknownFields::EMPTY,
+        })
+    }
+}
+
+impl<'a> Config<'a> {
+    /// Returns the config a device starts out with, ex: for first-boot provisioning without
+    /// parsing a string: the current [`FORMAT_VERSION`], an active slot holding `image` at
+    /// `version`, an [empty](PassiveConf::is_empty) passive slot and no other sections.
+    pub const fn default_for(image: ImageLabel<'a>, version: u32) -> Config<'a> {
+        Config {
+            format_version: FORMAT_VERSION,
+            board: None,
+            active: ActiveConf {
+                active_config: ConfigKeys::Active,
+                image_name: image,
+                image_dir: None,
+                image_version: version,
+                image_semver: None,
+                image_hash: None,
+                channel: None,
+                min_hwrev: None,
+            },
+            passive: PassiveConf::empty(),
+            recovery: None,
+            settings: None,
+            #[cfg(feature = "multi-slot")]
+            extra_passive: heapless::Vec::new(),
+        }
+    }
+
+    /// Returns the active-image configuration.
+    pub fn active(&self) -> &ActiveConf<'a> {
+        &self.active
+    }
+
+    /// Returns the passive-image configuration.
+    pub fn passive(&self) -> &PassiveConf<'a> {
+        &self.passive
+    }
+
+    /// Returns every passive-image configuration: [`passive`](Config::passive) followed by
+    /// any further `[passive]` components (with the `multi-slot` feature).
+    pub fn passive_slots(&self) -> impl Iterator<Item = &PassiveConf<'a>> {
+        core::iter::once(&self.passive).chain(self.extra_passive())
+    }
+
+    #[cfg(feature = "multi-slot")]
+    fn extra_passive(&self) -> &[PassiveConf<'a>] {
+        &self.extra_passive
+    }
+
+    #[cfg(not(feature = "multi-slot"))]
+    fn extra_passive(&self) -> &[PassiveConf<'a>] {
+        &[]
+    }
+
+    /// Decides which slot the bootloader should boot. The passive slot is chosen only if all
+    /// of the following hold, otherwise the active slot is:
+    ///
+    /// - its `ready_for_update_flag` is `true`,
+    /// - it holds a newer image than the active slot (see [`passive_is_newer`]),
+    /// - its `update_status` isn't [`UpdateStatus::Failed`], nor an [`UpdateStatus::Unknown`]
+    ///   the bootloader can't judge.
+    ///
+    /// A [dry run](PassiveConf::is_dry_run) is booted like any other passive image, but the
+    /// caller must not commit it afterwards.
+    pub fn boot_target(&self) -> BootTarget {
+        let passive = &self.passive;
+        if passive.ready_for_update_flag
+            && passive_is_newer(&self.active, passive)
+            && !matches!(
+                passive.update_status,
+                Some(UpdateStatus::Failed | UpdateStatus::Unknown(_))
+            )
+        {
+            BootTarget::Passive
+        } else {
+            BootTarget::Active
+        }
+    }
+
+    /// Returns `true` if the image [`boot_target`](Config::boot_target) picks may boot on a
+    /// board of revision `hwrev`, ex: the `[board]` section's
+    /// [`hwrev`](BoardConf::hwrev). See [`ActiveConf::allows_hwrev`]. The
+    /// [`recovery`](Config::recovery) image, which is only booted when neither slot can be,
+    /// is checked with [`RecoveryConf::allows_hwrev`].
+    pub fn image_allowed(&self, hwrev: u32) -> bool {
+        match self.boot_target() {
+            BootTarget::Active => self.active.allows_hwrev(hwrev),
+            BootTarget::Passive => self.passive.allows_hwrev(hwrev),
         }
     }
-}
-
-fn config_keys(input: &str) -> IResult<&str, ConfigKeys> {
-    alt((tag("[active]"), tag("[passive]")))(input)
-        .map(|(next_input, res)| (next_input, res.into()))
-}
 
-fn image_name(input: &str) -> IResult<&str, ImageLabel> {
-    preceded(
-        tag("image_name="),
-        tuple((alphanumericwithhypen, tag(".itb"))),
-    )(input)
-    .map(|(next_input, res)| (next_input, res))
-}
+    /// Returns the config after a successful A/B swap: the passive image becomes the active
+    /// one, and the passive slot is left empty (see [`PassiveConf::is_empty`]). Every other
+    /// section is kept as is.
+    ///
+    /// Fails with [`ConfigError::NotPromotable`] unless the passive status is `Success` or
+    /// `Committed`, with [`ConfigError::DryRun`] if the passive slot is a
+    /// [dry run](PassiveConf::is_dry_run), and with [`ConfigError::MissingField`] if the
+    /// passive slot has no image name or version to promote.
+    pub fn promote(&self) -> Result<Config<'a>, ConfigError<'a>> {
+        let passive = &self.passive;
+        if !matches!(
+            passive.update_status,
+            Some(UpdateStatus::Success | UpdateStatus::Committed)
+        ) {
+            return Err(ConfigError::NotPromotable(passive.update_status));
+        }
+        if passive.is_dry_run() {
+            return Err(ConfigError::DryRun);
+        }
+        let image_name = passive
+            .image_name
+            .ok_or(ConfigError::MissingField(IMAGE_NAME_KEY))?;
+        let image_version = passive
+            .image_version
+            .ok_or(ConfigError::MissingField(IMAGE_VERSION_KEY))?;
+        let mut cleared = passive.clone();
+        cleared.clear();
+        Ok(Config {
+            active: ActiveConf {
+                active_config: self.active.active_config,
+                image_name,
+                image_dir: passive.image_dir,
+                image_version,
+                image_semver: passive.image_semver,
+                image_hash: passive.image_hash,
+                channel: passive.channel,
+                min_hwrev: passive.min_hwrev,
+            },
+            passive: cleared,
+            ..self.clone()
+        })
+    }
+
+    /// Feeds the config's canonical form (see [`canonicalize_into`]) to `state`, so two
+    /// configs that differ only in formatting, comments or unknown keys hash the same.
+    pub fn semantic_hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        struct HashWriter<'h, H>(&'h mut H);
+
+        impl<H: core::hash::Hasher> fmt::Write for HashWriter<'_, H> {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                self.0.write(s.as_bytes());
+                Ok(())
+            }
+        }
+
+        // neither the writer nor the `Display` impls ever fail
+        let _ = fmt::write(&mut HashWriter(state), format_args!("{}", self));
+    }
+
+    /// Returns `true` if the configs are equal, as by `==`, except perhaps for the passive
+    /// slots' `update_status`. Ex: an updater can skip a flash write that would only change
+    /// the status.
+    pub fn eq_ignoring_status(&self, other: &Config) -> bool {
+        fn without_status<'c>(config: &Config<'c>) -> Config<'c> {
+            let mut config = config.clone();
+            config.passive.update_status = None;
+            #[cfg(feature = "multi-slot")]
+            for passive in config.extra_passive.iter_mut() {
+                passive.update_status = None;
+            }
+            config
+        }
+        without_status(self) == without_status(other)
+    }
+
+    /// Checks the config against rules that the grammar alone can't enforce, returning the
+    /// first rule that's violated. Currently:
+    ///
+    /// - the active and passive sections must not name the same image file
+    ///   ([`ConfigError::DuplicateImageName`]).
+    pub fn validate(&self) -> Result<(), ConfigError<'a>> {
+        VALIDATION_RULES.iter().try_for_each(|rule| rule(self))
+    }
+
+    /// Copies the borrowed strings out of the input, so the result no longer depends on it.
+    #[cfg(feature = "alloc")]
+    pub fn into_owned(self) -> OwnedConfig {
+        let to_owned = |dir: Option<&str>, (name, ext): ImageLabel| {
+            (dir.map(str::to_string), name.to_string(), ext.to_string())
+        };
+        let blank_passive = |passive: &PassiveConf| PassiveConf {
+            passive_config: passive.passive_config,
+            ready_for_update_flag: passive.ready_for_update_flag,
+            image_name: None,
+            image_dir: None,
+            image_version: passive.image_version,
+            image_semver: passive.image_semver,
+            image_hash: passive.image_hash,
+            channel: passive.channel,
+            min_hwrev: passive.min_hwrev,
+            update_status: passive
+                .update_status
+                .map(|status| status.known().unwrap_or(UpdateStatus::Unknown(""))),
+            boot_attempts: passive.boot_attempts,
+            test_window_secs: passive.test_window_secs,
+            dry_run: passive.dry_run,
+            staged_at: passive.staged_at,
+            retry_backoff: passive.retry_backoff,
+            unknown_fields: UnknownFields::EMPTY,
+        };
+        let unknown_status = |passive: &PassiveConf| match passive.update_status {
+            Some(UpdateStatus::Unknown(status)) => Some(status.to_string()),
+            _ => None,
+        };
+        let unknown_lines = |passive: &PassiveConf| {
+            passive
+                .unknown_fields
+                .iter()
+                .map(|(key, value)| format!("{}={}\n", key, value))
+                .collect::<String>()
+        };
+        let active = &self.active;
+        OwnedConfig {
+            board_model: self.board.as_ref().map(|board| board.model.to_string()),
+            active_name: to_owned(active.image_dir, active.image_name),
+            passive_name: self
+                .passive
+                .image_name
+                .map(|label| to_owned(self.passive.image_dir, label)),
+            recovery_name: self
+                .recovery
+                .as_ref()
+                .map(|recovery| to_owned(recovery.image_dir, recovery.image_name)),
+            #[cfg(feature = "multi-slot")]
+            extra_passive_names: self
+                .extra_passive
+                .iter()
+                .map(|passive| {
+                    passive
+                        .image_name
+                        .map(|label| to_owned(passive.image_dir, label))
+                })
+                .collect(),
+            passive_status: unknown_status(&self.passive),
+            #[cfg(feature = "multi-slot")]
+            extra_passive_statuses: self.extra_passive.iter().map(unknown_status).collect(),
+            passive_unknown: unknown_lines(&self.passive),
+            #[cfg(feature = "multi-slot")]
+            extra_passive_unknown: self.extra_passive.iter().map(unknown_lines).collect(),
+            config: Config {
+                format_version: self.format_version,
+                board: self.board.as_ref().map(|board| BoardConf {
+                    board_config: board.board_config,
+                    model: "",
+                    hwrev: board.hwrev,
+                }),
+                active: ActiveConf {
+                    active_config: active.active_config,
+                    image_name: ("", ""),
+                    image_dir: None,
+                    image_version: active.image_version,
+                    image_semver: active.image_semver,
+                    image_hash: active.image_hash,
+                    channel: active.channel,
+                    min_hwrev: active.min_hwrev,
+                },
+                passive: blank_passive(&self.passive),
+                recovery: self.recovery.as_ref().map(|recovery| RecoveryConf {
+                    recovery_config: recovery.recovery_config,
+                    image_name: ("", ""),
+                    image_dir: None,
+                    image_version: recovery.image_version,
+                    image_semver: recovery.image_semver,
+                    image_hash: recovery.image_hash,
+                    min_hwrev: recovery.min_hwrev,
+                }),
+                settings: self.settings,
+                #[cfg(feature = "multi-slot")]
+                extra_passive: self.extra_passive.iter().map(blank_passive).collect(),
+            },
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl OwnedConfig {
+    /// Returns a borrowed [`Config`] view of this configuration.
+    pub fn as_config(&self) -> Config<'_> {
+        let mut config = self.config.clone();
+        let (dir, name, ext) = &self.active_name;
+        config.active.image_dir = dir.as_deref();
+        config.active.image_name = (name, ext);
+        if let Some((dir, name, ext)) = &self.passive_name {
+            config.passive.image_dir = dir.as_deref();
+            config.passive.image_name = Some((name, ext));
+        }
+        if let Some(status) = &self.passive_status {
+            config.passive.update_status = Some(UpdateStatus::Unknown(status));
+        }
+        config.passive.unknown_fields = UnknownFields {
+            text: &self.passive_unknown,
+        };
+        if let (Some(board), Some(model)) = (&mut config.board, &self.board_model) {
+            board.model = model;
+        }
+        if let (Some(recovery), Some((dir, name, ext))) =
+            (&mut config.recovery, &self.recovery_name)
+        {
+            recovery.image_dir = dir.as_deref();
+            recovery.image_name = (name, ext);
+        }
+        #[cfg(feature = "multi-slot")]
+        for (passive, name) in config
+            .extra_passive
+            .iter_mut()
+            .zip(&self.extra_passive_names)
+        {
+            if let Some((dir, name, ext)) = name {
+                passive.image_dir = dir.as_deref();
+                passive.image_name = Some((name, ext));
+            }
+        }
+        #[cfg(feature = "multi-slot")]
+        for (passive, status) in config
+            .extra_passive
+            .iter_mut()
+            .zip(&self.extra_passive_statuses)
+        {
+            if let Some(status) = status {
+                passive.update_status = Some(UpdateStatus::Unknown(status));
+            }
+        }
+        #[cfg(feature = "multi-slot")]
+        for (passive, text) in config
+            .extra_passive
+            .iter_mut()
+            .zip(&self.extra_passive_unknown)
+        {
+            passive.unknown_fields = UnknownFields { text };
+        }
+        config
+    }
+}
+
+/// A label consists of a `filename` and a file extension (ex: `.itb`)
+pub type ImageLabel<'a> = (&'a str, &'a str);
+
+/// Displays an [`ImageLabel`] as the file name it came from (ex: `signed-apertis-rpi4.itb`),
+/// without allocating.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayLabel<'a>(pub ImageLabel<'a>);
+
+impl fmt::Display for DisplayLabel<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, ext) = self.0;
+        write!(f, "{}{}", name, ext)
+    }
+}
+
+/// The format of an image, as given by the extension of its [`ImageLabel`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat<'a> {
+    /// A FIT image (`.itb`).
+    Itb,
+    /// A FIT image (`.fit`).
+    Fit,
+    /// A raw binary (`.bin`).
+    Bin,
+    /// A legacy U-Boot image (`.uImage`).
+    UImage,
+    /// Any other extension, as it appeared in the input.
+    Other(&'a str),
+}
+
+impl<'a> From<&'a str> for ImageFormat<'a> {
+    /// Maps an extension, including its leading `.`, to a format.
+    fn from(ext: &'a str) -> Self {
+        match ext {
+            ".itb" => ImageFormat::Itb,
+            ".fit" => ImageFormat::Fit,
+            ".bin" => ImageFormat::Bin,
+            ".uImage" => ImageFormat::UImage,
+            _ => ImageFormat::Other(ext),
+        }
+    }
+}
+
+/// (De)serializes an [`ImageLabel`] as a `{"name": .., "ext": ..}` object.
+#[cfg(feature = "serde")]
+mod serde_label {
+    use super::ImageLabel;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct Label<'a> {
+        name: &'a str,
+        ext: &'a str,
+    }
+
+    pub fn serialize<S: Serializer>(label: &ImageLabel, s: S) -> Result<S::Ok, S::Error> {
+        let (name, ext) = *label;
+        Label { name, ext }.serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<ImageLabel<'de>, D::Error> {
+        Label::deserialize(d).map(|label| (label.name, label.ext))
+    }
+
+    /// The same, for an optional label.
+    pub mod option {
+        use super::{ImageLabel, Label};
+        use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            label: &Option<ImageLabel>,
+            s: S,
+        ) -> Result<S::Ok, S::Error> {
+            label.map(|(name, ext)| Label { name, ext }).serialize(s)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            d: D,
+        ) -> Result<Option<ImageLabel<'de>>, D::Error> {
+            Option::<Label>::deserialize(d).map(|label| label.map(|label| (label.name, label.ext)))
+        }
+    }
+}
+
+/// A [`SignatureBlock`] as it's deserialized, before its `sig` is checked.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct SignatureFields<'a> {
+    alg: &'a str,
+    sig: &'a str,
+}
+
+#[cfg(feature = "serde")]
+impl<'a> TryFrom<SignatureFields<'a>> for SignatureBlock<'a> {
+    type Error = ConfigError<'a>;
+
+    fn try_from(SignatureFields { alg, sig }: SignatureFields<'a>) -> Result<Self, Self::Error> {
+        if !is_base64(sig) {
+            return Err(ConfigError::InvalidSignature(sig));
+        }
+        Ok(SignatureBlock { alg, sig })
+    }
+}
+
+/// Errors that can occur while interpreting a configuration file. This is also the error
+/// type carried by the crate's nom combinators, so a failure deep inside a section is
+/// reported with its cause rather than as a bare nom [`ErrorKind`].
+#[derive(Debug, PartialEq)]
+pub enum ConfigError<'a> {
+    /// A section header other than `[board]`, `[active]`, `[passive]`, `[recovery]` or
+    /// `[settings]` was encountered, or a `[board]` header that isn't the first section.
+    UnknownSection(&'a str),
+    /// An `update_status` value other than `updating`, `testing`, `success`, `committed` or
+    /// `failed` was encountered.
+    InvalidStatus(&'a str),
+    /// A `channel` value other than `stable`, `beta` or `dev` was encountered.
+    UnknownChannel(&'a str),
+    /// An `image_version` value could not be read as a version number.
+    InvalidVersion(&'a str),
+    /// An `image_hash` value is not exactly 64 hex characters.
+    InvalidHash(&'a str),
+    /// A `[signature]` section's `sig` value isn't padded base64.
+    InvalidSignature(&'a str),
+    /// A `retry_backoff_secs` value isn't a comma-separated list of up to
+    /// [`MAX_BACKOFF_STEPS`] numbers of seconds.
+    InvalidBackoff(&'a str),
+    /// `expand_vars` met a `${NAME}` its resolver doesn't know. Holds the name, or the
+    /// text from an unterminated `${`.
+    UndefinedVariable(&'a str),
+    /// A numeric field's value doesn't fit its type (ex: a `boot_attempts` past `u32::MAX`).
+    /// Holds the field's key and the value.
+    NumberTooLarge(&'static str, &'a str),
+    /// The active and passive sections name the same image file. Holds the active image's
+    /// name.
+    DuplicateImageName(&'a str),
+    /// A mandatory field is absent from its section. Holds the name of the field.
+    MissingField(&'static str),
+    /// The same section appears twice. Holds the input starting at the repeated section.
+    DuplicateSection(&'a str),
+    /// Keys that only a `[passive]` component has (ex: `ready_for_update_flag`) appear
+    /// before any `[passive]` header. Holds the input starting at the first such key.
+    MissingSection(&'a str),
+    /// The same optional field appears twice in a section. Holds the input starting at the
+    /// repeated line.
+    DuplicateField(&'a str),
+    /// Something other than whitespace or comments follows the parsed config. Holds the
+    /// unconsumed input.
+    TrailingInput(&'a str),
+    /// The input ended before the config was complete.
+    Incomplete,
+    /// A [`ConfigStreamParser`] was fed more input than its buffer holds.
+    BufferFull,
+    /// The input is longer than the limit given to [`parse_config_bounded`].
+    InputTooLarge,
+    /// The input has more unknown keys than the `heapless::Vec` given to
+    /// `parse_with_extra_keys` holds.
+    TooManyExtraKeys,
+    /// The input is not valid UTF-8.
+    InvalidUtf8,
+    /// [`Config::promote`] was called on a passive slot whose status isn't `success` or
+    /// `committed`. Holds the status.
+    NotPromotable(Option<UpdateStatus<'a>>),
+    /// [`Config::promote`] was called on a passive slot marked as a
+    /// [dry run](PassiveConf::is_dry_run), which must never be committed.
+    DryRun,
+    /// The `version=` line names a format version newer than [`FORMAT_VERSION`] (or `0`).
+    /// Holds the version.
+    UnsupportedFormatVersion(&'a str),
+    /// The input does not match the config grammar.
+    Parse(Error<&'a str>),
+}
+
+impl<'a> ConfigError<'a> {
+    /// Lowers this error to the plain nom error returned by [`parse_config`], at its
+    /// `position`.
+    fn into_nom_error(self, input: &'a str) -> Error<&'a str> {
+        match self {
+            ConfigError::Parse(e) => e,
+            e => {
+                let (at, kind) = e.position(input);
+                Error::new(at, kind)
+            }
+        }
+    }
+
+    /// Where this error occurred and the nom [`ErrorKind`] closest to it. Errors that don't
+    /// carry a position are reported at `input`.
+    fn position(&self, input: &'a str) -> (&'a str, ErrorKind) {
+        match *self {
+            ConfigError::UnknownSection(i)
+            | ConfigError::InvalidStatus(i)
+            | ConfigError::UnknownChannel(i) => (i, ErrorKind::Tag),
+            ConfigError::InvalidVersion(i) => (i, ErrorKind::MapRes),
+            ConfigError::InvalidHash(i) => (i, ErrorKind::HexDigit),
+            ConfigError::InvalidSignature(i) => (i, ErrorKind::Verify),
+            ConfigError::InvalidBackoff(i) => (i, ErrorKind::Digit),
+            ConfigError::UndefinedVariable(i) => (i, ErrorKind::Verify),
+            ConfigError::NumberTooLarge(_, i) => (i, ErrorKind::TooLarge),
+            ConfigError::UnsupportedFormatVersion(i) => (i, ErrorKind::Verify),
+            ConfigError::MissingField(_) => (input, ErrorKind::Tag),
+            ConfigError::DuplicateSection(i) | ConfigError::DuplicateField(i) => {
+                (i, ErrorKind::Permutation)
+            }
+            ConfigError::MissingSection(i) => (i, ErrorKind::Tag),
+            ConfigError::DuplicateImageName(i) => (i, ErrorKind::Verify),
+            ConfigError::TrailingInput(i) => (i, ErrorKind::Eof),
+            ConfigError::Incomplete => (input, ErrorKind::Complete),
+            ConfigError::BufferFull
+            | ConfigError::InputTooLarge
+            | ConfigError::TooManyExtraKeys => (input, ErrorKind::TooLarge),
+            ConfigError::InvalidUtf8 => (input, ErrorKind::Char),
+            ConfigError::NotPromotable(_) | ConfigError::DryRun => (input, ErrorKind::Verify),
+            ConfigError::Parse(ref e) => (e.input, e.code),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Writes `message`, followed by the first line of `input` if there is one. Variants
+        // that hold the rest of the input would otherwise print the whole file.
+        fn describe(f: &mut fmt::Formatter<'_>, message: &str, input: &str) -> fmt::Result {
+            let line = input.split(['\r', '\n']).next().unwrap_or_default();
+            if line.is_empty() {
+                f.write_str(message)
+            } else {
+                write!(f, "{}: {}", message, line)
+            }
+        }
+
+        match self {
+            ConfigError::UnknownSection(i) => describe(f, "unknown section", i),
+            ConfigError::InvalidStatus(i) => describe(f, "invalid update status", i),
+            ConfigError::UnknownChannel(i) => describe(f, "unknown channel", i),
+            ConfigError::InvalidVersion(i) => describe(f, "invalid version number", i),
+            ConfigError::InvalidHash(i) => describe(f, "invalid image hash", i),
+            ConfigError::InvalidSignature(i) => describe(f, "invalid signature", i),
+            ConfigError::InvalidBackoff(i) => describe(f, "invalid retry backoff", i),
+            ConfigError::UndefinedVariable(i) => describe(f, "undefined variable", i),
+            ConfigError::NumberTooLarge(key, i) => write!(f, "{} out of range: {}", key, i),
+            ConfigError::DuplicateImageName(i) => {
+                describe(f, "active and passive images have the same name", i)
+            }
+            ConfigError::MissingField(key) => describe(f, "missing field", key),
+            ConfigError::DuplicateSection(i) => describe(f, "duplicate section", i),
+            ConfigError::MissingSection(i) => describe(f, "key outside its section", i),
+            ConfigError::DuplicateField(i) => describe(f, "duplicate field", i),
+            ConfigError::TrailingInput(i) => describe(f, "unexpected input after config", i),
+            ConfigError::Incomplete => f.write_str("unexpected end of input"),
+            ConfigError::BufferFull => f.write_str("stream parser buffer is full"),
+            ConfigError::InputTooLarge => f.write_str("input is too large"),
+            ConfigError::TooManyExtraKeys => f.write_str("too many unknown keys"),
+            ConfigError::InvalidUtf8 => f.write_str("input is not valid UTF-8"),
+            ConfigError::NotPromotable(Some(status)) => {
+                write!(f, "passive image can't be promoted from status {}", status)
+            }
+            ConfigError::NotPromotable(None) => {
+                f.write_str("passive image can't be promoted without a status")
+            }
+            ConfigError::DryRun => f.write_str("passive image is a dry run and can't be promoted"),
+            ConfigError::UnsupportedFormatVersion(i) => {
+                describe(f, "unsupported format version", i)
+            }
+            ConfigError::Parse(e) => describe(f, "syntax error", e.input),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ConfigError<'_> {}
+
+/// A [`ConfigError`] along with the 1-based line and column at which it occurred.
+#[derive(Debug, PartialEq)]
+pub struct LocatedError<'a> {
+    /// The underlying error.
+    pub error: ConfigError<'a>,
+    /// The line of the input at which the error occurred.
+    pub line: usize,
+    /// The column (in characters) of that line at which the error occurred.
+    pub column: usize,
+}
+
+impl<'a> LocatedError<'a> {
+    /// Locates `error` within `input`, the text it was produced from. Errors that don't
+    /// carry a position (ex: [`ConfigError::MissingField`], [`ConfigError::Incomplete`]) are
+    /// located at the end of the input.
+    fn new(input: &'a str, error: ConfigError<'a>) -> Self {
+        let remainder = match &error {
+            ConfigError::UnknownSection(i)
+            | ConfigError::InvalidStatus(i)
+            | ConfigError::UnknownChannel(i)
+            | ConfigError::InvalidVersion(i)
+            | ConfigError::InvalidHash(i)
+            | ConfigError::InvalidSignature(i)
+            | ConfigError::InvalidBackoff(i)
+            | ConfigError::UndefinedVariable(i)
+            | ConfigError::NumberTooLarge(_, i)
+            | ConfigError::DuplicateSection(i)
+            | ConfigError::MissingSection(i)
+            | ConfigError::DuplicateField(i)
+            | ConfigError::UnsupportedFormatVersion(i)
+            | ConfigError::DuplicateImageName(i)
+            | ConfigError::TrailingInput(i) => i,
+            ConfigError::Parse(e) => e.input,
+            ConfigError::MissingField(_)
+            | ConfigError::Incomplete
+            | ConfigError::BufferFull
+            | ConfigError::InputTooLarge
+            | ConfigError::TooManyExtraKeys
+            | ConfigError::NotPromotable(_)
+            | ConfigError::DryRun
+            | ConfigError::InvalidUtf8 => "",
+        };
+        let consumed = input.get(..offset_in(input, remainder)).unwrap_or(input);
+        let line_start = consumed.rfind('\n').map_or(0, |i| i + 1);
+        LocatedError {
+            error,
+            line: consumed.matches('\n').count() + 1,
+            column: consumed[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+/// A [`LocatedError`] that owns its message, so it can outlive the input it was produced
+/// from. Returned by `OwnedConfig`'s [`FromStr`] impl.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnedConfigError {
+    /// The underlying error, as [`ConfigError`] displays it.
+    pub message: String,
+    /// The line of the input at which the error occurred.
+    pub line: usize,
+    /// The column (in characters) of that line at which the error occurred.
+    pub column: usize,
+}
+
+#[cfg(feature = "alloc")]
+impl From<LocatedError<'_>> for OwnedConfigError {
+    fn from(located: LocatedError<'_>) -> Self {
+        OwnedConfigError {
+            message: located.error.to_string(),
+            line: located.line,
+            column: located.column,
+        }
+    }
+}
+
+/// Displays the error as `line:column: message`.
+#[cfg(feature = "alloc")]
+impl fmt::Display for OwnedConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for OwnedConfigError {}
+
+/// Returns the offset of `slice` within `input`. Every slice the parsers return points into
+/// their input, so this is a pointer difference; anything else is placed at the end.
+fn offset_in(input: &str, slice: &str) -> usize {
+    (slice.as_ptr() as usize)
+        .checked_sub(input.as_ptr() as usize)
+        .filter(|offset| *offset <= input.len())
+        .unwrap_or(input.len())
+}
+
+impl<'a> ParseError<&'a str> for ConfigError<'a> {
+    fn from_error_kind(input: &'a str, kind: ErrorKind) -> Self {
+        ConfigError::Parse(Error::new(input, kind))
+    }
+
+    fn append(_: &'a str, _: ErrorKind, other: Self) -> Self {
+        other
+    }
+
+    /// When alternatives fail, a specific cause wins over a plain grammar mismatch.
+    fn or(self, other: Self) -> Self {
+        match (&self, &other) {
+            (ConfigError::Parse(_), _) => other,
+            (_, ConfigError::Parse(_)) => self,
+            _ => other,
+        }
+    }
+}
+
+/// Context is dropped: a [`ConfigError`] already names what went wrong.
+impl<'a> ContextError<&'a str> for ConfigError<'a> {}
+
+impl<'a> FromExternalError<&'a str, ConfigError<'a>> for ConfigError<'a> {
+    fn from_external_error(_: &'a str, _: ErrorKind, e: ConfigError<'a>) -> Self {
+        e
+    }
+}
+
+impl<'a> FromExternalError<&'a str, ParseIntError> for ConfigError<'a> {
+    fn from_external_error(input: &'a str, kind: ErrorKind, _: ParseIntError) -> Self {
+        ConfigError::Parse(Error::new(input, kind))
+    }
+}
+
+impl<'a> From<Err<ConfigError<'a>>> for ConfigError<'a> {
+    fn from(e: Err<ConfigError<'a>>) -> Self {
+        match e {
+            Err::Error(e) | Err::Failure(e) => e,
+            Err::Incomplete(_) => ConfigError::Incomplete,
+        }
+    }
+}
+
+impl<'a> From<Err<Error<&'a str>>> for ConfigError<'a> {
+    fn from(e: Err<Error<&'a str>>) -> Self {
+        match e {
+            Err::Error(e) | Err::Failure(e) => ConfigError::Parse(e),
+            Err::Incomplete(_) => ConfigError::Incomplete,
+        }
+    }
+}
+
+/// The result of the crate's nom combinators. The grammar is generic over its error type
+/// (see [`parse_config_as`]) but is [`ConfigError`] everywhere else.
+type ParseResult<'a, O, E = ConfigError<'a>> = IResult<&'a str, O, E>;
+
+/// The error types the grammar can run with. The crate's own failures reach `E` as a
+/// [`ConfigError`] through `FromExternalError`, at the position they occurred (see
+/// `config_error`). Every type with the three nom traits is one, ex: nom's `VerboseError`.
+trait ConfigParseError<'a>:
+    ParseError<&'a str> + ContextError<&'a str> + FromExternalError<&'a str, ConfigError<'a>>
+{
+}
+
+impl<'a, E> ConfigParseError<'a> for E where
+    E: ParseError<&'a str> + ContextError<&'a str> + FromExternalError<&'a str, ConfigError<'a>>
+{
+}
+
+/// Raises `error` as an `E`, with `input` as the position of errors that don't carry one.
+/// For a [`ConfigError`] this is `error` itself.
+fn config_error<'a, E: ConfigParseError<'a>>(input: &'a str, error: ConfigError<'a>) -> E {
+    let (at, kind) = error.position(input);
+    E::from_external_error(at, kind, error)
+}
+
+/// The header of the board section.
+pub const BOARD_HEADER: &str = "[board]";
+/// The header of the active section.
+pub const ACTIVE_HEADER: &str = "[active]";
+/// The header of the passive section.
+pub const PASSIVE_HEADER: &str = "[passive]";
+/// The header of the recovery section.
+pub const RECOVERY_HEADER: &str = "[recovery]";
+/// The header of the settings section.
+pub const SETTINGS_HEADER: &str = "[settings]";
+/// The header of the signature section a signed config ends with.
+pub const SIGNATURE_HEADER: &str = "[signature]";
+
+/// The key of the `image_name` field.
+pub const IMAGE_NAME_KEY: &str = "image_name";
+/// The key of the `image_version` field.
+pub const IMAGE_VERSION_KEY: &str = "image_version";
+/// The key of the `image_hash` field.
+pub const IMAGE_HASH_KEY: &str = "image_hash";
+/// The key of the passive section's `ready_for_update_flag` field.
+pub const READY_FOR_UPDATE_KEY: &str = "ready_for_update_flag";
+/// The key of the passive section's `update_status` field.
+pub const UPDATE_STATUS_KEY: &str = "update_status";
+/// The key of the `channel` field.
+pub const CHANNEL_KEY: &str = "channel";
+/// The key of the `min_hwrev` field.
+pub const MIN_HWREV_KEY: &str = "min_hwrev";
+/// The key of the passive section's `boot_attempts` field.
+pub const BOOT_ATTEMPTS_KEY: &str = "boot_attempts";
+/// The key of the passive section's `test_window_secs` field.
+pub const TEST_WINDOW_SECS_KEY: &str = "test_window_secs";
+/// The key of the passive section's `dry_run` field.
+pub const DRY_RUN_KEY: &str = "dry_run";
+/// The key of the passive section's `staged_at` field.
+pub const STAGED_AT_KEY: &str = "staged_at";
+/// The key of the passive section's `retry_backoff_secs` field.
+pub const RETRY_BACKOFF_KEY: &str = "retry_backoff_secs";
+/// The key of the settings section's `watchdog_timeout` field.
+pub const WATCHDOG_TIMEOUT_KEY: &str = "watchdog_timeout";
+/// The key of the settings section's `auto_rollback` field.
+pub const AUTO_ROLLBACK_KEY: &str = "auto_rollback";
+/// The key of the board section's `model` field.
+pub const MODEL_KEY: &str = "model";
+/// The key of the board section's `hwrev` field.
+pub const HWREV_KEY: &str = "hwrev";
+/// The key of the signature section's `alg` field.
+pub const ALG_KEY: &str = "alg";
+/// The key of the signature section's `sig` field.
+pub const SIG_KEY: &str = "sig";
+/// The prefix of an `image_version` value (ex: `ver_1.2.3`).
+pub const VERSION_PREFIX: &str = "ver_";
+/// The key of the optional format version line at the top of a config file.
+pub const FORMAT_VERSION_KEY: &str = "version";
+
+/// The newest config format version this crate understands.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// The format version of a file without a `version=` line.
+pub const DEFAULT_FORMAT_VERSION: u32 = 1;
+
+/// The line that separates one config from the next in a batch file read by
+/// [`parse_many`].
+pub const RECORD_SEPARATOR: &str = "---";
+
+/// A cheap shape check that can run in `const` context, ex: to reject a typo in a config
+/// embedded in firmware at build time:
+///
+/// ```
+/// const DEFAULT_CONFIG: &str = "[active]\nimage_name=a.itb\nimage_version=ver_1\n\
+///                               [passive]\nready_for_update_flag=false\n";
+/// const _: () = assert!(rparser::is_plausible_config(DEFAULT_CONFIG));
+/// ```
+///
+/// Returns `true` if `input` contains the [`ACTIVE_HEADER`] and [`PASSIVE_HEADER`] and the
+/// keys every valid config has ([`IMAGE_NAME_KEY`], [`IMAGE_VERSION_KEY`] and
+/// [`READY_FOR_UPDATE_KEY`]). nom's combinators aren't `const fn`s, so this can't run the
+/// real grammar: it doesn't check that the keys are in the right sections, that values are
+/// well-formed, or anything about comments. A `true` result therefore doesn't mean
+/// [`parse`] will succeed; a `false` one does mean it will fail.
+pub const fn is_plausible_config(input: &str) -> bool {
+    const fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        let mut start = 0;
+        while start + needle.len() <= haystack.len() {
+            let mut i = 0;
+            while i < needle.len() && haystack[start + i] == needle[i] {
+                i += 1;
+            }
+            if i == needle.len() {
+                return true;
+            }
+            start += 1;
+        }
+        false
+    }
+    let input = input.as_bytes();
+    contains(input, ACTIVE_HEADER.as_bytes())
+        && contains(input, PASSIVE_HEADER.as_bytes())
+        && contains(input, IMAGE_NAME_KEY.as_bytes())
+        && contains(input, IMAGE_VERSION_KEY.as_bytes())
+        && contains(input, READY_FOR_UPDATE_KEY.as_bytes())
+}
+
+impl<'a> TryFrom<&'a str> for ConfigKeys {
+    type Error = ConfigError<'a>;
+
+    fn try_from(i: &'a str) -> Result<Self, Self::Error> {
+        match i {
+            BOARD_HEADER => Ok(ConfigKeys::Board),
+            ACTIVE_HEADER => Ok(ConfigKeys::Active),
+            PASSIVE_HEADER => Ok(ConfigKeys::Passive),
+            RECOVERY_HEADER => Ok(ConfigKeys::Recovery),
+            SETTINGS_HEADER => Ok(ConfigKeys::Settings),
+            _ => Err(ConfigError::UnknownSection(i)),
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a str> for UpdateStatus<'a> {
+    type Error = ConfigError<'a>;
+
+    /// Status keywords are matched case-insensitively. Never returns
+    /// [`UpdateStatus::Unknown`].
+    fn try_from(i: &'a str) -> Result<Self, Self::Error> {
+        STATUS_TABLE
+            .iter()
+            .find(|(keyword, _)| i.eq_ignore_ascii_case(keyword))
+            .map(|(_, status)| *status)
+            .ok_or(ConfigError::InvalidStatus(i))
+    }
+}
+
+/// The `update_status` keyword for each known [`UpdateStatus`], used to parse the field.
+const STATUS_TABLE: &[(&str, UpdateStatus<'static>)] = &[
+    ("updating", UpdateStatus::Updating),
+    ("testing", UpdateStatus::Testing),
+    ("success", UpdateStatus::Success),
+    ("committed", UpdateStatus::Committed),
+    ("failed", UpdateStatus::Failed),
+];
+
+impl<'a> TryFrom<&'a str> for Channel {
+    type Error = ConfigError<'a>;
+
+    /// Channel keywords are matched case-insensitively.
+    fn try_from(i: &'a str) -> Result<Self, Self::Error> {
+        CHANNEL_TABLE
+            .iter()
+            .find(|(keyword, _)| i.eq_ignore_ascii_case(keyword))
+            .map(|(_, channel)| *channel)
+            .ok_or(ConfigError::UnknownChannel(i))
+    }
+}
+
+/// The `channel` keyword for each [`Channel`], used both to parse and to emit the field.
+const CHANNEL_TABLE: &[(&str, Channel)] = &[
+    ("stable", Channel::Stable),
+    ("beta", Channel::Beta),
+    ("dev", Channel::Dev),
+];
+
+impl From<ConfigKeys> for &'static str {
+    /// Returns the section header, the inverse of `ConfigKeys::try_from`.
+    fn from(key: ConfigKeys) -> Self {
+        key.header()
+    }
+}
+
+impl ConfigKeys {
+    fn header(&self) -> &'static str {
+        match self {
+            ConfigKeys::Board => BOARD_HEADER,
+            ConfigKeys::Active => ACTIVE_HEADER,
+            ConfigKeys::Passive => PASSIVE_HEADER,
+            ConfigKeys::Recovery => RECOVERY_HEADER,
+            ConfigKeys::Settings => SETTINGS_HEADER,
+        }
+    }
+}
+
+impl<'a> UpdateStatus<'a> {
+    /// Returns the `update_status` keyword for this status (ex: `"testing"`), as accepted by
+    /// the parser. An [`UpdateStatus::Unknown`] returns its text as it appeared in the input.
+    pub const fn as_str(&self) -> &'a str {
+        match self {
+            UpdateStatus::Updating => "updating",
+            UpdateStatus::Testing => "testing",
+            UpdateStatus::Success => "success",
+            UpdateStatus::Committed => "committed",
+            UpdateStatus::Failed => "failed",
+            UpdateStatus::Unknown(keyword) => keyword,
+        }
+    }
+
+    /// Returns this status if it's one this crate knows, without the input's lifetime.
+    fn known(&self) -> Option<UpdateStatus<'static>> {
+        STATUS_TABLE
+            .iter()
+            .map(|(_, known)| *known)
+            .find(|known| known == self)
+    }
+
+    /// Returns `true` if an update may move from this status to `next`. The only legal
+    /// paths are `Updating -> Testing -> Success -> Committed` and
+    /// `Updating -> Testing -> Failed`, one step at a time; going backwards, skipping a step,
+    /// leaving `Committed` or `Failed`, or "moving" to the same status is rejected. Nothing
+    /// moves to or from an [`UpdateStatus::Unknown`].
+    pub fn can_transition_to(&self, next: UpdateStatus) -> bool {
+        matches!(
+            (self, next),
+            (UpdateStatus::Updating, UpdateStatus::Testing)
+                | (UpdateStatus::Testing, UpdateStatus::Success)
+                | (UpdateStatus::Testing, UpdateStatus::Failed)
+                | (UpdateStatus::Success, UpdateStatus::Committed)
+        )
+    }
+}
+
+// Statuses order by how far through the update they are,
+// `Updating < Testing < Success < Committed`.
+// `Failed` sorts below `Updating`, so that slots needing attention come first, and
+// `Unknown` below that, ordered by its text.
+impl Ord for UpdateStatus<'_> {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        fn progress(status: &UpdateStatus) -> u8 {
+            match status {
+                UpdateStatus::Unknown(_) => 0,
+                UpdateStatus::Failed => 1,
+                UpdateStatus::Updating => 2,
+                UpdateStatus::Testing => 3,
+                UpdateStatus::Success => 4,
+                UpdateStatus::Committed => 5,
+            }
+        }
+        match (self, other) {
+            (UpdateStatus::Unknown(a), UpdateStatus::Unknown(b)) => a.cmp(b),
+            _ => progress(self).cmp(&progress(other)),
+        }
+    }
+}
+
+impl PartialOrd for UpdateStatus<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for UpdateStatus<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Channel {
+    /// Returns the `channel` keyword for this channel (ex: `"beta"`), as accepted by the
+    /// parser.
+    pub const fn as_str(&self) -> &'static str {
+        let mut i = 0;
+        while i < CHANNEL_TABLE.len() {
+            let (keyword, channel) = CHANNEL_TABLE[i];
+            if channel as u8 == *self as u8 {
+                return keyword;
+            }
+            i += 1;
+        }
+        ""
+    }
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Display for ImageVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Writes an `image_name=` line, quoting the name if it holds characters an unquoted name
+/// can't.
+fn write_name(f: &mut fmt::Formatter<'_>, dir: Option<&str>, label: ImageLabel<'_>) -> fmt::Result {
+    let (name, ext) = label;
+    let dir = dir.unwrap_or("");
+    if dir.chars().all(is_path_char)
+        && name
+            .chars()
+            .chain(ext.chars())
+            .all(|c| c.is_ascii_alphanumeric() || ['-', '_', '.'].contains(&c))
+    {
+        writeln!(f, "image_name={}{}", dir, DisplayLabel(label))
+    } else {
+        writeln!(f, "image_name=\"{}{}\"", dir, DisplayLabel(label))
+    }
+}
+
+fn write_hash(f: &mut fmt::Formatter<'_>, hash: Option<[u8; 32]>) -> fmt::Result {
+    if let Some(hash) = hash {
+        write!(f, "image_hash=")?;
+        for byte in hash {
+            write!(f, "{:02x}", byte)?;
+        }
+        writeln!(f)?;
+    }
+    Ok(())
+}
+
+fn write_channel(f: &mut fmt::Formatter<'_>, channel: Option<Channel>) -> fmt::Result {
+    match channel {
+        Some(channel) => writeln!(f, "channel={}", channel),
+        None => Ok(()),
+    }
+}
+
+fn write_min_hwrev(f: &mut fmt::Formatter<'_>, min_hwrev: Option<u32>) -> fmt::Result {
+    match min_hwrev {
+        Some(min_hwrev) => writeln!(f, "min_hwrev={}", min_hwrev),
+        None => Ok(()),
+    }
+}
+
+fn write_version(
+    f: &mut fmt::Formatter<'_>,
+    version: u32,
+    semver: Option<ImageVersion>,
+) -> fmt::Result {
+    match semver {
+        Some(semver) => writeln!(f, "image_version=ver_{}", semver),
+        None => writeln!(f, "image_version=ver_{}", version),
+    }
+}
+
+/// Emits the `[active]` block in the form accepted by [`parse_config`].
+impl fmt::Display for ActiveConf<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.active_config.header())?;
+        write_name(f, self.image_dir, self.image_name)?;
+        write_version(f, self.image_version, self.image_semver)?;
+        write_hash(f, self.image_hash)?;
+        write_channel(f, self.channel)?;
+        write_min_hwrev(f, self.min_hwrev)
+    }
+}
+
+/// Emits the `[board]` block in the form accepted by [`parse`].
+impl fmt::Display for BoardConf<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.board_config.header())?;
+        writeln!(f, "model={}", self.model)?;
+        writeln!(f, "hwrev={}", self.hwrev)
+    }
+}
+
+/// Emits the `[recovery]` block in the form accepted by [`parse`].
+impl fmt::Display for RecoveryConf<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.recovery_config.header())?;
+        write_name(f, self.image_dir, self.image_name)?;
+        write_version(f, self.image_version, self.image_semver)?;
+        write_hash(f, self.image_hash)?;
+        write_min_hwrev(f, self.min_hwrev)
+    }
+}
+
+/// Emits the `[passive]` block in the form accepted by [`parse_config`]. Fields that
+/// are `None` are omitted.
+impl fmt::Display for PassiveConf<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.passive_config.header())?;
+        writeln!(f, "ready_for_update_flag={}", self.ready_for_update_flag)?;
+        if let Some(label) = self.image_name {
+            write_name(f, self.image_dir, label)?;
+        }
+        if let Some(version) = self.image_version {
+            write_version(f, version, self.image_semver)?;
+        }
+        write_hash(f, self.image_hash)?;
+        write_channel(f, self.channel)?;
+        write_min_hwrev(f, self.min_hwrev)?;
+        if let Some(status) = self.update_status {
+            writeln!(f, "update_status={}", status.as_str())?;
+        }
+        if let Some(attempts) = self.boot_attempts {
+            writeln!(f, "boot_attempts={}", attempts)?;
+        }
+        if let Some(secs) = self.test_window_secs {
+            writeln!(f, "test_window_secs={}", secs)?;
+        }
+        if let Some(dry_run) = self.dry_run {
+            writeln!(f, "dry_run={}", dry_run)?;
+        }
+        if let Some(secs) = self.staged_at {
+            writeln!(f, "staged_at={}", secs)?;
+        }
+        if let Some(backoff) = self.retry_backoff {
+            f.write_str("retry_backoff_secs=")?;
+            for (i, secs) in backoff.as_slice().iter().enumerate() {
+                let sep = if i == 0 { "" } else { "," };
+                write!(f, "{}{}", sep, secs)?;
+            }
+            writeln!(f)?;
+        }
+        for (key, value) in self.unknown_fields.iter() {
+            writeln!(f, "{}={}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SettingsConf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.settings_config.header())?;
+        if let Some(timeout) = self.watchdog_timeout {
+            writeln!(f, "watchdog_timeout={}", timeout)?;
+        }
+        if let Some(rollback) = self.auto_rollback {
+            writeln!(f, "auto_rollback={}", rollback)?;
+        }
+        Ok(())
+    }
+}
+
+/// Emits the whole file in the form accepted by [`parse`], which parses it back to the same
+/// [`Config`]. Comments are dropped, but the passive sections'
+/// [unknown fields](PassiveConf::unknown_fields) are kept. The `version=` line is left out
+/// when the format version is [`DEFAULT_FORMAT_VERSION`]. With the `alloc` feature, this
+/// also provides `Config::to_string()`.
+impl fmt::Display for Config<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.format_version != DEFAULT_FORMAT_VERSION {
+            writeln!(f, "version={}", self.format_version)?;
+        }
+        if let Some(board) = &self.board {
+            writeln!(f, "{}", board)?;
+        }
+        write!(f, "{}\n{}", self.active, self.passive)?;
+        for passive in self.extra_passive() {
+            write!(f, "\n{}", passive)?;
+        }
+        if let Some(recovery) = &self.recovery {
+            write!(f, "\n{}", recovery)?;
+        }
+        if let Some(settings) = &self.settings {
+            write!(f, "\n{}", settings)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns `true` only if the passive slot holds an image that is strictly newer than the
+/// active one. Versions are compared as [`ImageVersion`]s, so `ver_1.10.0` is newer than
+/// `ver_1.2.3`; a passive section without a version is never newer.
+pub fn passive_is_newer(active: &ActiveConf, passive: &PassiveConf) -> bool {
+    passive
+        .image_semver()
+        .is_some_and(|version| version > active.image_semver())
+}
+
+/// Compares two configs field by field, ex: the one read before an update and the one read
+/// after it.
+pub fn diff(old: &Config, new: &Config) -> ConfigDiff {
+    let (old_active, new_active) = (&old.active, &new.active);
+    let (old_passive, new_passive) = (&old.passive, &new.passive);
+    ConfigDiff {
+        board: old.board != new.board,
+        active_name: (old_active.image_dir, old_active.image_name)
+            != (new_active.image_dir, new_active.image_name),
+        active_version: (old_active.image_version, old_active.image_semver)
+            != (new_active.image_version, new_active.image_semver),
+        active_hash: old_active.image_hash != new_active.image_hash,
+        active_channel: old_active.channel != new_active.channel,
+        active_min_hwrev: old_active.min_hwrev != new_active.min_hwrev,
+        passive_flag: old_passive.ready_for_update_flag != new_passive.ready_for_update_flag,
+        passive_name: (old_passive.image_dir, old_passive.image_name)
+            != (new_passive.image_dir, new_passive.image_name),
+        passive_version: (old_passive.image_version, old_passive.image_semver)
+            != (new_passive.image_version, new_passive.image_semver),
+        passive_hash: old_passive.image_hash != new_passive.image_hash,
+        passive_channel: old_passive.channel != new_passive.channel,
+        passive_min_hwrev: old_passive.min_hwrev != new_passive.min_hwrev,
+        passive_status: old_passive.update_status != new_passive.update_status,
+        boot_attempts: old_passive.boot_attempts != new_passive.boot_attempts,
+        test_window_secs: old_passive.test_window_secs != new_passive.test_window_secs,
+        dry_run: old_passive.dry_run != new_passive.dry_run,
+        staged_at: old_passive.staged_at != new_passive.staged_at,
+        retry_backoff: old_passive.retry_backoff != new_passive.retry_backoff,
+        recovery: old.recovery != new.recovery,
+        settings: old.settings != new.settings,
+    }
+}
+
+/// Serializes the active and passive components into a config file that
+/// [`parse_config`] accepts.
+#[cfg(feature = "alloc")]
+pub fn config_to_string(active: &ActiveConf, passive: &PassiveConf) -> String {
+    format!("{}\n{}", active, passive)
+}
+
+/// Like [`canonicalize_into`], but returns the canonical form as a [`String`].
+#[cfg(feature = "alloc")]
+pub fn canonicalize(input: &str) -> Result<String, ConfigError<'_>> {
+    parse_canonical(input).map(|config| config.to_string())
+}
+
+/// Parses `input` for [`canonicalize_into`], without its unknown fields.
+fn parse_canonical(input: &str) -> Result<Config<'_>, ConfigError<'_>> {
+    let mut config = parse(input)?;
+    config.passive.unknown_fields = UnknownFields::EMPTY;
+    #[cfg(feature = "multi-slot")]
+    for passive in config.extra_passive.iter_mut() {
+        passive.unknown_fields = UnknownFields::EMPTY;
+    }
+    Ok(config)
+}
+
+/// Parses `input` and writes it into `buf` in canonical form: sections in a fixed order, one
+/// `key=value` line per field with no extra spaces, lowercase keywords, decimal versions and
+/// `\n` line endings. Comments and unknown keys are dropped. Two configs that parse to the
+/// same [`Config`] canonicalize to the same bytes, ex: for signing.
+///
+/// Returns the part of `buf` that was written, or [`ConfigError::BufferFull`] if the
+/// canonical form doesn't fit.
+pub fn canonicalize_into<'a, 'b>(
+    input: &'a str,
+    buf: &'b mut [u8],
+) -> Result<&'b str, ConfigError<'a>> {
+    let config = parse_canonical(input)?;
+    let mut writer = SliceWriter { buf, len: 0 };
+    fmt::write(&mut writer, format_args!("{}", config)).map_err(|_| ConfigError::BufferFull)?;
+    let SliceWriter { buf, len } = writer;
+    // only whole `str`s are ever copied in, so this can't fail
+    core::str::from_utf8(&buf[..len]).map_err(|_| ConfigError::InvalidUtf8)
+}
+
+/// A [`fmt::Write`] into a fixed buffer that fails once the buffer is full.
+struct SliceWriter<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+}
+
+impl fmt::Write for SliceWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        self.buf
+            .get_mut(self.len..end)
+            .ok_or(fmt::Error)?
+            .copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn config_keys(input: &str) -> ParseResult<'_, ConfigKeys> {
+    config_keys_with(input, &SectionNames::DEFAULT)
+}
+
+fn config_keys_with<'a, E: ConfigParseError<'a>>(
+    input: &'a str,
+    names: &SectionNames,
+) -> ParseResult<'a, ConfigKeys, E> {
+    let (next_input, header) = section_header(input)?;
+    let name = &header[1..header.len() - 1];
+    match name {
+        _ if name == names.board => Ok((next_input, ConfigKeys::Board)),
+        _ if name == names.active => Ok((next_input, ConfigKeys::Active)),
+        _ if name == names.passive => Ok((next_input, ConfigKeys::Passive)),
+        _ if name == names.recovery => Ok((next_input, ConfigKeys::Recovery)),
+        _ if name == names.settings => Ok((next_input, ConfigKeys::Settings)),
+        _ => Err(Err::Error(config_error(
+            input,
+            ConfigError::UnknownSection(header),
+        ))),
+    }
+}
+
+/// The header line of the `key` section, named as in `names`.
+fn header_with<'a, E: ConfigParseError<'a>>(
+    input: &'a str,
+    names: &SectionNames,
+    key: ConfigKeys,
+) -> ParseResult<'a, ConfigKeys, E> {
+    line(verify(|i| config_keys_with(i, names), |res| *res == key))(input)
+}
+
+/// Any `[name]` section header, known or not.
+fn section_header<'a, E: ConfigParseError<'a>>(input: &'a str) -> ParseResult<'a, &'a str, E> {
+    recognize(tuple((
+        tag("["),
+        take_till(|c: char| c == ']' || c.is_whitespace()),
+        tag("]"),
+    )))(input)
+}
+
+#[cfg(test)]
+fn image_name(input: &str) -> ParseResult<'_, ImageLabel<'_>> {
+    image_name_with_ext(input, &[".itb"])
+}
+
+#[cfg(any(test, feature = "alloc"))]
+fn image_path<'a, E: ConfigParseError<'a>>(
+    input: &'a str,
+) -> ParseResult<'a, (Option<&'a str>, ImageLabel<'a>), E> {
+    image_value(input, ParseOptions::DEFAULT.image_extensions, true)
+}
+
+/// Parses an `image_name=` line whose file extension is one of `exts` (ex: `&[".itb", ".fit"]`).
+/// Names may contain alphanumerics, `-`, `_` and `.`; the extension starts at the *last* dot
+/// where one of `exts` matches, so `foo.bar.itb` is the name `foo.bar` with extension `.itb`.
+/// When more than one extension matches, the longest one wins. The returned [`ImageLabel`]
+/// holds the extension as it appeared in the input.
+///
+/// A name may also be double-quoted (ex: `image_name="my image.itb"`), in which case it may
+/// contain any characters other than line breaks, and must end with its extension. A quote
+/// or backslash inside the name is escaped as `\"` or `\\`. The returned label borrows the
+/// name as written, escapes included, so use `unescape` (with the `alloc` feature) to get at
+/// the actual name.
+pub fn image_name_with_ext<'a>(input: &'a str, exts: &[&str]) -> ParseResult<'a, ImageLabel<'a>> {
+    image_value(input, exts, false).map(|(next_input, (_, label))| (next_input, label))
+}
+
+/// Like [`image_name_with_ext`], but the name may also be a path, ex:
+/// `image_name=/boot/signed-apertis-rpi4.itb` or `image_name=C:\images\rpi4.itb`. Everything
+/// up to the last `/` or `\` is returned as the directory, separator included, and the rest
+/// is split into an [`ImageLabel`] as usual. A bare name has no directory. This is what the
+/// config parsers use.
+pub fn image_path_with_ext<'a>(
+    input: &'a str,
+    exts: &[&str],
+) -> ParseResult<'a, (Option<&'a str>, ImageLabel<'a>)> {
+    image_value(input, exts, true)
+}
+
+/// Whether `c` may appear in an unquoted `image_name` path.
+fn is_path_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || ['-', '_', '.', '/', '\\', ':'].contains(&c)
+}
+
+/// Splits `path` after its last separator, if `paths` are allowed and it has one. An escaped
+/// quote (`\"`) isn't a separator; an escaped backslash (`\\`) is one.
+fn split_dir(path: &str, paths: bool) -> (Option<&str>, &str) {
+    let mut end = None;
+    let mut chars = path.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '/' => end = Some(i + 1),
+            '\\' if path[i + 1..].starts_with('"') => {
+                chars.next();
+            }
+            '\\' if path[i + 1..].starts_with('\\') => {
+                chars.next();
+                end = Some(i + 2);
+            }
+            '\\' => end = Some(i + 1),
+            _ => {}
+        }
+    }
+    match end.filter(|_| paths) {
+        Some(end) => (Some(&path[..end]), &path[end..]),
+        None => (None, path),
+    }
+}
+
+/// The offset of the quote that closes a quoted value, skipping escaped quotes and
+/// backslashes. `None` if the line ends first.
+fn closing_quote(quoted: &str) -> Option<usize> {
+    let mut chars = quoted.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some(i),
+            '\r' | '\n' => return None,
+            '\\' if quoted[i + 1..].starts_with(['"', '\\']) => {
+                chars.next();
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Resolves the `\"` and `\\` escapes of a quoted `image_name` (ex: the name or directory of
+/// an [`ImageLabel`]). Text without escapes, which includes every unquoted name, is returned
+/// as is, without copying. A backslash before any other character is kept.
+#[cfg(feature = "alloc")]
+pub fn unescape(raw: &str) -> Cow<'_, str> {
+    if !raw.contains("\\\"") && !raw.contains("\\\\") {
+        return Cow::Borrowed(raw);
+    }
+    let mut unescaped = String::with_capacity(raw.len());
+    let mut chars = raw.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some(&next @ ('"' | '\\'))) => {
+                unescaped.push(next);
+                chars.next();
+            }
+            _ => unescaped.push(c),
+        }
+    }
+    Cow::Owned(unescaped)
+}
+
+/// Replaces each `${NAME}` in `input` with `resolver(NAME)`, ex: `image_name=${BOARD}.itb`,
+/// so that the result can be parsed as usual. This is plain text substitution run before
+/// parsing: it applies to comments too, and substituted values aren't expanded again.
+/// Input without a `${` is returned as is, without copying.
+///
+/// Fails with [`ConfigError::UndefinedVariable`] if the resolver returns `None` or a `${`
+/// isn't closed on the same line.
+#[cfg(feature = "alloc")]
+pub fn expand_vars<'a, 'v>(
+    input: &'a str,
+    resolver: impl Fn(&str) -> Option<&'v str>,
+) -> Result<Cow<'a, str>, ConfigError<'a>> {
+    if !input.contains("${") {
+        return Ok(Cow::Borrowed(input));
+    }
+    let mut expanded = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let name_start = &rest[start + 2..];
+        let end = name_start
+            .find(['}', '\n'])
+            .filter(|&end| name_start[end..].starts_with('}'))
+            .ok_or(ConfigError::UndefinedVariable(&rest[start..]))?;
+        let name = &name_start[..end];
+        expanded.push_str(resolver(name).ok_or(ConfigError::UndefinedVariable(name))?);
+        rest = &name_start[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(Cow::Owned(expanded))
+}
+
+fn image_value<'a, E: ConfigParseError<'a>>(
+    input: &'a str,
+    exts: &[&str],
+    paths: bool,
+) -> ParseResult<'a, (Option<&'a str>, ImageLabel<'a>), E> {
+    key_value(IMAGE_NAME_KEY, |i| image_label(i, exts, paths))(input)
+}
+
+/// The value of an `image_name=` line, see [`image_path_with_ext`].
+fn image_label<'a, E: ConfigParseError<'a>>(
+    value_input: &'a str,
+    exts: &[&str],
+    paths: bool,
+) -> ParseResult<'a, (Option<&'a str>, ImageLabel<'a>), E> {
+    if let Some(quoted) = value_input.strip_prefix('"') {
+        let (path, next_input) = closing_quote(quoted)
+            .map(|i| (&quoted[..i], &quoted[i + 1..]))
+            .ok_or_else(|| Err::Failure(E::from_error_kind(value_input, ErrorKind::Char)))?;
+        let (dir, label) = split_dir(path, paths);
+        let (name_len, _) = split_ext(label, label, exts)
+            .filter(|(name_len, ext_len)| name_len + ext_len == label.len())
+            .ok_or_else(|| {
+                let at = label.rfind('.').unwrap_or(label.len());
+                Err::Error(E::from_error_kind(&label[at..], ErrorKind::Tag))
+            })?;
+        return Ok((next_input, (dir, label.split_at(name_len))));
+    }
+    let path_len = value_input
+        .find(|c| !is_path_char(c))
+        .unwrap_or(value_input.len());
+    let (dir, _) = split_dir(&value_input[..path_len], paths);
+    let value_input = &value_input[dir.map_or(0, str::len)..];
+    let (_, label) = name_chars(&['-', '_', '.'])(value_input)?;
+    let (name_len, ext_len) = split_ext(value_input, label, exts).ok_or_else(|| {
+        let at = label.rfind('.').unwrap_or(label.len());
+        Err::Error(E::from_error_kind(&value_input[at..], ErrorKind::Tag))
+    })?;
+    let (name, next_input) = value_input.split_at(name_len);
+    let (ext, next_input) = next_input.split_at(ext_len);
+    Ok((next_input, (dir, (name, ext))))
+}
+
+/// Like [`image_name_with_ext`], but also rejects names that are almost always a bug in
+/// whatever wrote them: names that start or end with `-` (ex: `-foo.itb`, `foo-.itb`) or
+/// contain `--`. The error points at the name.
+pub fn image_name_strict<'a>(input: &'a str, exts: &[&str]) -> ParseResult<'a, ImageLabel<'a>> {
+    let (next_input, (name, ext)) = image_name_with_ext(input, exts)?;
+    if name.starts_with('-') || name.ends_with('-') || name.contains("--") {
+        return Err(Err::Error(ConfigError::from_error_kind(
+            name,
+            ErrorKind::Verify,
+        )));
+    }
+    Ok((next_input, (name, ext)))
+}
+
+/// Finds where the extension of `label`, a prefix of `input`, starts and how long it is.
+/// See [`image_name_with_ext`].
+fn split_ext(input: &str, label: &str, exts: &[&str]) -> Option<(usize, usize)> {
+    let ext_len = |at: &str| {
+        exts.iter()
+            .filter(|ext| at.starts_with(**ext))
+            .map(|ext| ext.len())
+            .max()
+    };
+    label
+        .match_indices('.')
+        .rev()
+        .filter(|(i, _)| *i > 0)
+        .find_map(|(i, _)| ext_len(&input[i..]).map(|len| (i, len)))
+}
+
+fn image_version<'a, E: ConfigParseError<'a>>(input: &'a str) -> ParseResult<'a, u32, E> {
+    key_value(
+        IMAGE_VERSION_KEY,
+        separated_pair(
+            tag("ver"),
+            tag("_"),
+            terminated(version_number, end_of_value),
+        ),
+    )(input)
+    .map(|(next_input, res)| (next_input, res.1))
+}
+
+/// The largest version number [`image_version_saturating`] produces.
+pub const MAX_VERSION: u32 = u32::MAX;
+
+/// Parses an `image_version=ver_<n>` line like the strict parser used by [`parse_config`], but
+/// clamps a number too large for a `u32` to [`MAX_VERSION`] instead of failing, ex: for
+/// legacy tools that emit oversized build numbers.
+pub fn image_version_saturating(input: &str) -> ParseResult<'_, u32> {
+    key_value(
+        IMAGE_VERSION_KEY,
+        preceded(
+            tag(VERSION_PREFIX),
+            terminated(saturating_version_number, end_of_value),
+        ),
+    )(input)
+}
+
+/// Like [`version_number`], but clamps to [`MAX_VERSION`] on overflow.
+fn saturating_version_number<'a, E: ConfigParseError<'a>>(
+    input: &'a str,
+) -> ParseResult<'a, u32, E> {
+    let (digits_input, hex) = opt(tag_no_case_ascii("0x"))(input)?;
+    let (radix, digits) = match hex {
+        Some(_) => (16, hex_digit1(digits_input)),
+        None => (10, digit1(digits_input)),
+    };
+    let (next_input, digits) = digits.map_err(|_: Err<E>| {
+        let value = input.split(char::is_whitespace).next().unwrap_or_default();
+        Err::Failure(config_error(input, ConfigError::InvalidVersion(value)))
+    })?;
+    let version = digits.chars().fold(0u32, |acc, c| {
+        let digit = c.to_digit(radix).unwrap_or_default();
+        acc.saturating_mul(radix).saturating_add(digit)
+    });
+    Ok((next_input, version))
+}
+
+fn semver_version<'a, E: ConfigParseError<'a>>(input: &'a str) -> ParseResult<'a, ImageVersion, E> {
+    key_value(
+        IMAGE_VERSION_KEY,
+        tuple((
+            preceded(tag(VERSION_PREFIX), version_number),
+            preceded(tag("."), version_number),
+            preceded(tag("."), version_number),
+            end_of_value,
+        )),
+    )(input)
+    .map(|(next_input, (major, minor, patch, _))| {
+        (
+            next_input,
+            ImageVersion {
+                major,
+                minor,
+                patch,
+            },
+        )
+    })
+}
+
+/// A non-empty run of decimal digits, or hex digits after a `0x` prefix, that must fit a
+/// `u32`. A missing or overflowing number is a hard failure rather than a reason to try
+/// other alternatives.
+fn version_number<'a, E: ConfigParseError<'a>>(input: &'a str) -> ParseResult<'a, u32, E> {
+    let (digits_input, hex) = opt(tag_no_case_ascii("0x"))(input)?;
+    let radix = if hex.is_some() { 16 } else { 10 };
+    let prefix_len = input.len() - digits_input.len();
+    number(digits_input, radix, |digits| {
+        ConfigError::InvalidVersion(&input[..prefix_len + digits.len()])
+    })
+    .map_err(|e| match e {
+        Err::Error(_) => {
+            let value = input.split(char::is_whitespace).next().unwrap_or_default();
+            Err::Failure(config_error(input, ConfigError::InvalidVersion(value)))
+        }
+        e => e,
+    })
+}
+
+/// A non-empty run of digits in `radix` (10 or 16) that must fit a `T`. A number that's too
+/// large is a hard failure with `too_large(digits)` rather than a reason to try other
+/// alternatives; a missing one is a soft error.
+fn number<'a, T: TryFrom<u64>, E: ConfigParseError<'a>>(
+    input: &'a str,
+    radix: u32,
+    too_large: impl FnOnce(&'a str) -> ConfigError<'a>,
+) -> ParseResult<'a, T, E> {
+    let (next_input, digits) = if radix == 16 {
+        hex_digit1(input)?
+    } else {
+        digit1(input)?
+    };
+    digits
+        .chars()
+        .try_fold(0u64, |acc, c| {
+            acc.checked_mul(u64::from(radix))?
+                .checked_add(u64::from(c.to_digit(radix)?))
+        })
+        .and_then(|res| T::try_from(res).ok())
+        .map(|res| (next_input, res))
+        .ok_or_else(|| Err::Failure(config_error(input, too_large(digits))))
+}
+
+/// The decimal value of the `key` field, failing with [`ConfigError::NumberTooLarge`] if
+/// it doesn't fit a `T`.
+fn number_field<'a, T: TryFrom<u64>, E: ConfigParseError<'a>>(
+    key: &'static str,
+) -> impl FnMut(&'a str) -> ParseResult<'a, T, E> {
+    key_value(key, move |i| {
+        number(i, 10, |digits| ConfigError::NumberTooLarge(key, digits))
+    })
+}
+
+/// Accepts either form of `image_version`, returning the integer version along with the
+/// full semantic version when one was given.
+fn any_version<'a, E: ConfigParseError<'a>>(
+    input: &'a str,
+) -> ParseResult<'a, (u32, Option<ImageVersion>), E> {
+    alt((
+        map(semver_version, |res| (res.major, Some(res))),
+        map(image_version, |res| (res, None)),
+    ))(input)
+}
+
+#[cfg(any(test, feature = "alloc"))]
+fn passive_name<'a, E: ConfigParseError<'a>>(
+    input: &'a str,
+) -> ParseResult<'a, Option<(Option<&'a str>, ImageLabel<'a>)>, E> {
+    passive_name_with_ext(input, ParseOptions::DEFAULT.image_extensions)
+}
+
+/// The passive section's `image_name`, which may also be left empty (`image_name=` followed
+/// by the end of the line) to indicate that no image is staged.
+fn passive_name_with_ext<'a, E: ConfigParseError<'a>>(
+    input: &'a str,
+    exts: &[&str],
+) -> ParseResult<'a, Option<(Option<&'a str>, ImageLabel<'a>)>, E> {
+    alt((
+        map(|i| image_value(i, exts, true), Some),
+        value(
+            None,
+            key_value(IMAGE_NAME_KEY, peek(alt((line_ending, eof, tag("#"))))),
+        ),
+    ))(input)
+}
+
+/// The passive section's `image_version`, which may also be the literal `none` to
+/// indicate that no version is staged yet.
+fn passive_version<'a, E: ConfigParseError<'a>>(
+    input: &'a str,
+) -> ParseResult<'a, Option<(u32, Option<ImageVersion>)>, E> {
+    alt((
+        value(
+            None,
+            key_value(IMAGE_VERSION_KEY, tag_no_case_ascii("none")),
+        ),
+        map(any_version, Some),
+    ))(input)
+}
+
+/// An `image_hash=` line holding a SHA-256 as exactly 64 hex characters, in either case
+/// (`Display` writes them in lowercase). Anything else is a hard failure rather than a reason
+/// to skip the line.
+fn image_hash<'a, E: ConfigParseError<'a>>(input: &'a str) -> ParseResult<'a, [u8; 32], E> {
+    word_field(IMAGE_HASH_KEY, |value| {
+        if value.len() != 64 || !value.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(ConfigError::InvalidHash(value));
+        }
+        let mut hash = [0; 32];
+        for (i, byte) in hash.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&value[2 * i..2 * i + 2], 16)
+                .map_err(|_| ConfigError::InvalidHash(value))?;
+        }
+        Ok(hash)
+    })(input)
+}
+
+#[cfg(any(test, feature = "alloc"))]
+fn update_status(input: &str) -> ParseResult<'_, Option<UpdateStatus<'_>>> {
+    update_status_with(input, false)
+}
+
+/// The passive section's `update_status`, which may also be the literal `none` (in any case)
+/// to indicate that nothing is staged. Like `update_status`, but with `lenient` set a
+/// non-empty keyword this crate doesn't know becomes an [`UpdateStatus::Unknown`] rather
+/// than an error.
+fn update_status_with<'a, E: ConfigParseError<'a>>(
+    input: &'a str,
+    lenient: bool,
+) -> ParseResult<'a, Option<UpdateStatus<'a>>, E> {
+    word_field(UPDATE_STATUS_KEY, |value| {
+        match UpdateStatus::try_from(value) {
+            _ if value.eq_ignore_ascii_case("none") => Ok(None),
+            Err(_) if lenient && !value.is_empty() => Ok(Some(UpdateStatus::Unknown(value))),
+            res => res.map(Some),
+        }
+    })(input)
+}
+
+fn channel<'a, E: ConfigParseError<'a>>(input: &'a str) -> ParseResult<'a, Channel, E> {
+    word_field(CHANNEL_KEY, Channel::try_from)(input)
+}
+
+/// The value of the `key` field, up to whitespace or a comment, as converted by `convert`.
+/// A value `convert` rejects is a hard failure rather than a reason to try other
+/// alternatives.
+fn word_field<'a, O, E: ConfigParseError<'a>>(
+    key: &'static str,
+    mut convert: impl FnMut(&'a str) -> Result<O, ConfigError<'a>>,
+) -> impl FnMut(&'a str) -> ParseResult<'a, O, E> {
+    key_value(key, move |i| {
+        let (next_input, value) = take_till(|c: char| c.is_whitespace() || c == '#')(i)?;
+        convert(value)
+            .map(|res| (next_input, res))
+            .map_err(|e| Err::Failure(config_error(i, e)))
+    })
+}
+
+fn min_hwrev<'a, E: ConfigParseError<'a>>(input: &'a str) -> ParseResult<'a, u32, E> {
+    number_field(MIN_HWREV_KEY)(input)
+}
+
+fn boot_attempts<'a, E: ConfigParseError<'a>>(input: &'a str) -> ParseResult<'a, u32, E> {
+    number_field(BOOT_ATTEMPTS_KEY)(input)
+}
+
+fn test_window_secs<'a, E: ConfigParseError<'a>>(input: &'a str) -> ParseResult<'a, u32, E> {
+    number_field(TEST_WINDOW_SECS_KEY)(input)
+}
+
+fn dry_run<'a, E: ConfigParseError<'a>>(input: &'a str) -> ParseResult<'a, bool, E> {
+    key_value(DRY_RUN_KEY, parse_bool)(input)
+}
+
+fn staged_at<'a, E: ConfigParseError<'a>>(input: &'a str) -> ParseResult<'a, u64, E> {
+    number_field(STAGED_AT_KEY)(input)
+}
+
+/// A comma-separated list of up to [`MAX_BACKOFF_STEPS`] delays in seconds, ex: `1,2,4,8`.
+fn retry_backoff<'a, E: ConfigParseError<'a>>(input: &'a str) -> ParseResult<'a, RetryBackoff, E> {
+    word_field(RETRY_BACKOFF_KEY, |value| {
+        let invalid = || ConfigError::InvalidBackoff(value);
+        let (mut secs, mut len) = ([0; MAX_BACKOFF_STEPS], 0);
+        for delay in value.split(',') {
+            let slot = secs.get_mut(len).ok_or_else(invalid)?;
+            // `u32::from_str` also takes a leading `+`
+            *slot = u32::from_str(delay)
+                .ok()
+                .filter(|_| delay.bytes().all(|b| b.is_ascii_digit()))
+                .ok_or_else(invalid)?;
+            len += 1;
+        }
+        Ok(RetryBackoff { secs, len })
+    })(input)
+}
+
+fn ready_for_update<'a, E: ConfigParseError<'a>>(input: &'a str) -> ParseResult<'a, bool, E> {
+    key_value(READY_FOR_UPDATE_KEY, parse_bool)(input)
+}
+
+fn watchdog_timeout<'a, E: ConfigParseError<'a>>(input: &'a str) -> ParseResult<'a, u32, E> {
+    number_field(WATCHDOG_TIMEOUT_KEY)(input)
+}
+
+fn auto_rollback<'a, E: ConfigParseError<'a>>(input: &'a str) -> ParseResult<'a, bool, E> {
+    key_value(AUTO_ROLLBACK_KEY, parse_bool)(input)
+}
+
+fn board_model<'a, E: ConfigParseError<'a>>(input: &'a str) -> ParseResult<'a, &'a str, E> {
+    key_value(MODEL_KEY, name_chars(&['-', '_', '.']))(input)
+}
+
+fn board_hwrev<'a, E: ConfigParseError<'a>>(input: &'a str) -> ParseResult<'a, u32, E> {
+    number_field(HWREV_KEY)(input)
+}
+
+/// Runs `parser` on the value of a `key=value` pair. Values are trimmed: spaces or tabs on
+/// either side of the `=` and after the value are skipped (ex: `image_version = ver_123  `),
+/// so they never change what's parsed. Only a quoted `image_name` keeps the spaces inside
+/// its quotes. Errors get `key` as context.
+fn key_value<'a, O, E: ConfigParseError<'a>>(
+    key: &'static str,
+    parser: impl FnMut(&'a str) -> ParseResult<'a, O, E>,
+) -> impl FnMut(&'a str) -> ParseResult<'a, O, E> {
+    context(
+        key,
+        preceded(
+            tuple((tag(key), space0, char('='), space0)),
+            terminated(parser, space0),
+        ),
+    )
+}
+
+/// A boolean spelled, in any case, `true`/`1`/`yes`/`on` or `false`/`0`/`no`/`off`.
+fn parse_bool<'a, E: ConfigParseError<'a>>(input: &'a str) -> ParseResult<'a, bool, E> {
+    terminated(
+        alt((
+            value(
+                true,
+                alt((
+                    tag_no_case_ascii("true"),
+                    tag("1"),
+                    tag_no_case_ascii("yes"),
+                    tag_no_case_ascii("on"),
+                )),
+            ),
+            value(
+                false,
+                alt((
+                    tag_no_case_ascii("false"),
+                    tag("0"),
+                    tag_no_case_ascii("no"),
+                    tag_no_case_ascii("off"),
+                )),
+            ),
+        )),
+        end_of_value,
+    )(input)
+}
+
+/// Like nom's `tag` but compares ASCII letters case-insensitively.
+fn tag_no_case_ascii<'a, E: ConfigParseError<'a>>(
+    t: &'static str,
+) -> impl Fn(&'a str) -> ParseResult<'a, &'a str, E> {
+    move |input: &'a str| match input.get(..t.len()) {
+        Some(res) if res.eq_ignore_ascii_case(t) => Ok((&input[t.len()..], res)),
+        _ => Err(Err::Error(E::from_error_kind(input, ErrorKind::Tag))),
+    }
+}
+
+/// A `#` comment running up to (but not including) the end of the line.
+fn comment<'a, E: ConfigParseError<'a>>(input: &'a str) -> ParseResult<'a, &'a str, E> {
+    recognize(preceded(char('#'), not_line_ending))(input)
+}
+
+/// Succeeds without consuming anything when `input` is at whitespace or the end of input,
+/// so that a value can't run straight into trailing garbage.
+fn end_of_value<'a, E: ConfigParseError<'a>>(input: &'a str) -> ParseResult<'a, (), E> {
+    alt((value((), eof), value((), peek(multispace1))))(input)
+}
+
+/// A line ending (`\n` or `\r\n`) or the end of input.
+fn line_ending_or_eof<'a, E: ConfigParseError<'a>>(input: &'a str) -> ParseResult<'a, &'a str, E> {
+    alt((line_ending, eof))(input)
+}
+
+/// The rest of a `key=value` line after its value: trailing spaces and an optional comment,
+/// up to and including the line ending.
+fn end_of_line<'a, E: ConfigParseError<'a>>(input: &'a str) -> ParseResult<'a, &'a str, E> {
+    recognize(tuple((space0, opt(comment), line_ending_or_eof)))(input)
+}
+
+/// Runs `parser` on a single line, skipping any blank or comment lines before it. The line
+/// must end right after the value, so a value can never be extended onto the next line.
+fn line<'a, O, E: ConfigParseError<'a>>(
+    parser: impl FnMut(&'a str) -> ParseResult<'a, O, E>,
+) -> impl FnMut(&'a str) -> ParseResult<'a, O, E> {
+    preceded(multispace_or_comment0, terminated(parser, end_of_line))
+}
+
+/// Whitespace interleaved with any number of full-line or trailing comments.
+fn multispace_or_comment0<'a, E: ConfigParseError<'a>>(
+    input: &'a str,
+) -> ParseResult<'a, &'a str, E> {
+    recognize(many0_count(alt((multispace1, comment))))(input)
+}
+
+/// Runs `parser` on a mandatory `key=value` line, reporting [`ConfigError::MissingField`]
+/// when the line isn't there at all.
+fn required<'a, O, E: ConfigParseError<'a>>(
+    key: &'static str,
+    mut parser: impl FnMut(&'a str) -> ParseResult<'a, O, E>,
+) -> impl FnMut(&'a str) -> ParseResult<'a, O, E> {
+    move |input: &'a str| match parser(input) {
+        Err(Err::Error(_)) if !input.starts_with(key) => Err(Err::Error(config_error(
+            input,
+            ConfigError::MissingField(key),
+        ))),
+        res => res,
+    }
+}
+
+fn active_config(input: &str) -> ParseResult<'_, ActiveConf<'_>> {
+    active_config_with(input, &SectionNames::DEFAULT, ParseOptions::default())
+}
+
+fn active_config_with<'a, E: ConfigParseError<'a>>(
+    input: &'a str,
+    names: &SectionNames,
+    options: ParseOptions,
+) -> ParseResult<'a, ActiveConf<'a>, E> {
+    const NAME: Option<&str> = Some(IMAGE_NAME_KEY);
+    const VERSION: Option<&str> = Some(IMAGE_VERSION_KEY);
+    let (i, active_config) = header_with(input, names, ConfigKeys::Active)?;
+    let name = |i| image_value(i, options.image_extensions, true);
+    let (i, (image_dir, image_name)) = line(required(IMAGE_NAME_KEY, name))(i)?;
+    let (i, _) = repeated_fields(i, &[NAME], options.duplicate_fields)?;
+    let (i, (image_version, image_semver)) = line(required(IMAGE_VERSION_KEY, any_version))(i)?;
+    let (i, _) = repeated_fields(i, &[NAME, VERSION], options.duplicate_fields)?;
+    let (i, image_hash) = opt(line(image_hash))(i)?;
+    let hash = image_hash.and(Some(IMAGE_HASH_KEY));
+    let (i, _) = repeated_fields(i, &[NAME, VERSION, hash], options.duplicate_fields)?;
+    let (i, channel) = opt(line(channel))(i)?;
+    let channel_key = channel.and(Some(CHANNEL_KEY));
+    let (i, _) = repeated_fields(
+        i,
+        &[NAME, VERSION, hash, channel_key],
+        options.duplicate_fields,
+    )?;
+    let (i, min_hwrev) = opt(line(min_hwrev))(i)?;
+    let seen = [
+        NAME,
+        VERSION,
+        hash,
+        channel_key,
+        min_hwrev.and(Some(MIN_HWREV_KEY)),
+    ];
+    let (next_input, _) = repeated_fields(i, &seen, options.duplicate_fields)?;
+    Ok((
+        next_input,
+        ActiveConf {
+            active_config,
+            image_name,
+            image_dir,
+            image_version,
+            image_semver,
+            image_hash,
+            channel,
+            min_hwrev,
+        },
+    ))
+}
+
+/// Deals with the lines after a section's fields that repeat one of the `seen` keys: with
+/// [`DuplicateFields::Reject`] the first one is a [`ConfigError::DuplicateField`], with
+/// [`DuplicateFields::KeepFirst`] they're all skipped. Stops at the first other line.
+fn repeated_fields<'a, E: ConfigParseError<'a>>(
+    input: &'a str,
+    seen: &[Option<&str>],
+    policy: DuplicateFields,
+) -> ParseResult<'a, (), E> {
+    let mut remaining = input;
+    loop {
+        let Ok((next_input, (key, _))) = line(raw_pair::<E>)(remaining) else {
+            return Ok((remaining, ()));
+        };
+        if !seen.contains(&Some(key)) {
+            return Ok((remaining, ()));
+        }
+        if policy == DuplicateFields::Reject {
+            let (field_line, _) = multispace_or_comment0(remaining)?;
+            let error = ConfigError::DuplicateField(field_line);
+            return Err(Err::Failure(config_error(field_line, error)));
+        }
+        remaining = next_input;
+    }
+}
+
+#[cfg(test)]
+fn board_config(input: &str) -> ParseResult<'_, BoardConf<'_>> {
+    board_config_with(input, &SectionNames::DEFAULT, ParseOptions::default())
+}
+
+fn board_config_with<'a, E: ConfigParseError<'a>>(
+    input: &'a str,
+    names: &SectionNames,
+    options: ParseOptions,
+) -> ParseResult<'a, BoardConf<'a>, E> {
+    let (i, board_config) = header_with(input, names, ConfigKeys::Board)?;
+    let (i, model) = line(required(MODEL_KEY, board_model))(i)?;
+    let (i, _) = repeated_fields(i, &[Some(MODEL_KEY)], options.duplicate_fields)?;
+    let (i, hwrev) = line(required(HWREV_KEY, board_hwrev))(i)?;
+    let (next_input, _) = repeated_fields(
+        i,
+        &[Some(MODEL_KEY), Some(HWREV_KEY)],
+        options.duplicate_fields,
+    )?;
+    Ok((
+        next_input,
+        BoardConf {
+            board_config,
+            model,
+            hwrev,
+        },
+    ))
+}
+
+#[cfg(test)]
+fn recovery_config(input: &str) -> ParseResult<'_, RecoveryConf<'_>> {
+    recovery_config_with(input, &SectionNames::DEFAULT, ParseOptions::default())
+}
+
+fn recovery_config_with<'a, E: ConfigParseError<'a>>(
+    input: &'a str,
+    names: &SectionNames,
+    options: ParseOptions,
+) -> ParseResult<'a, RecoveryConf<'a>, E> {
+    const NAME: Option<&str> = Some(IMAGE_NAME_KEY);
+    const VERSION: Option<&str> = Some(IMAGE_VERSION_KEY);
+    let (i, recovery_config) = header_with(input, names, ConfigKeys::Recovery)?;
+    let name = |i| image_value(i, options.image_extensions, true);
+    let (i, (image_dir, image_name)) = line(required(IMAGE_NAME_KEY, name))(i)?;
+    let (i, _) = repeated_fields(i, &[NAME], options.duplicate_fields)?;
+    let (i, (image_version, image_semver)) = line(required(IMAGE_VERSION_KEY, any_version))(i)?;
+    let (i, _) = repeated_fields(i, &[NAME, VERSION], options.duplicate_fields)?;
+    let (i, image_hash) = opt(line(image_hash))(i)?;
+    let hash = image_hash.and(Some(IMAGE_HASH_KEY));
+    let (i, _) = repeated_fields(i, &[NAME, VERSION, hash], options.duplicate_fields)?;
+    let (i, min_hwrev) = opt(line(min_hwrev))(i)?;
+    let seen = [NAME, VERSION, hash, min_hwrev.and(Some(MIN_HWREV_KEY))];
+    let (next_input, _) = repeated_fields(i, &seen, options.duplicate_fields)?;
+    Ok((
+        next_input,
+        RecoveryConf {
+            recovery_config,
+            image_name,
+            image_dir,
+            image_version,
+            image_semver,
+            image_hash,
+            min_hwrev,
+        },
+    ))
+}
+
+fn passive_config(input: &str) -> ParseResult<'_, PassiveConf<'_>> {
+    passive_config_with(input, &SectionNames::DEFAULT, ParseOptions::default())
+}
+
+fn passive_config_with<'a, E: ConfigParseError<'a>>(
+    input: &'a str,
+    names: &SectionNames,
+    options: ParseOptions,
+) -> ParseResult<'a, PassiveConf<'a>, E> {
+    let (mut remaining, (passive_config, ready_for_update_flag)) = tuple((
+        |i| header_with(i, names, ConfigKeys::Passive),
+        line(required(READY_FOR_UPDATE_KEY, ready_for_update)),
+    ))(input)?;
+    let (mut image_name, mut version, mut image_hash) = (None, None, None);
+    let (mut update_status, mut boot_attempts, mut test_window_secs) = (None, None, None);
+    let (mut channel, mut dry_run, mut staged_at) = (None, None, None);
+    let (mut min_hwrev, mut retry_backoff) = (None, None);
+    let fields = remaining;
+    loop {
+        let (next_input, _) = unknown_keys(PASSIVE_KEYS)(remaining)?;
+        remaining = next_input;
+        let (next_input, field) = match passive_field(remaining, options) {
+            Ok(res) => res,
+            Err(Err::Error(_)) => break,
+            Err(e) => return Err(e),
+        };
+        // `set_once` keeps the first value, for `DuplicateFields::KeepFirst`
+        let duplicate = match field {
+            PassiveField::Name(res) => set_once(&mut image_name, res),
+            PassiveField::Version(res) => set_once(&mut version, res),
+            PassiveField::Hash(res) => set_once(&mut image_hash, res),
+            PassiveField::Channel(res) => set_once(&mut channel, res),
+            PassiveField::MinHwrev(res) => set_once(&mut min_hwrev, res),
+            PassiveField::Status(res) => set_once(&mut update_status, res),
+            PassiveField::BootAttempts(res) => set_once(&mut boot_attempts, res),
+            PassiveField::TestWindow(res) => set_once(&mut test_window_secs, res),
+            PassiveField::DryRun(res) => set_once(&mut dry_run, res),
+            PassiveField::StagedAt(res) => set_once(&mut staged_at, res),
+            PassiveField::RetryBackoff(res) => set_once(&mut retry_backoff, res),
+        };
+        if duplicate && options.duplicate_fields == DuplicateFields::Reject {
+            let (field_line, _) = multispace_or_comment0(remaining)?;
+            let error = ConfigError::DuplicateField(field_line);
+            return Err(Err::Failure(config_error(field_line, error)));
+        }
+        remaining = next_input;
+    }
+    let (next_input, _) = multispace_or_comment0(remaining)?;
+    let fields = &fields[..fields.len() - next_input.len()];
+
+    let (image_dir, mut image_name) = image_name.flatten().unzip();
+    let (mut image_version, image_semver) = version.flatten().unzip();
+    let mut image_semver = image_semver.flatten();
+    let mut update_status = update_status.flatten();
+
+    match (image_name, image_version, &update_status) {
+        (None, _, _) => (image_version, update_status) = (None, None),
+        (_, None, _) => (image_name, update_status) = (None, None),
+        (_, _, &None) => (image_name, image_version) = (None, None),
+        (_, _, _) => {}
+    }
+    let image_dir = image_dir.flatten().filter(|_| image_name.is_some());
+    if image_version.is_none() {
+        image_semver = None;
+        image_hash = None;
+    }
+    Ok((
+        next_input,
+        PassiveConf {
+            passive_config,
+            ready_for_update_flag,
+            image_name,
+            image_dir,
+            image_version,
+            image_semver,
+            image_hash,
+            channel,
+            min_hwrev,
+            update_status,
+            boot_attempts,
+            test_window_secs,
+            dry_run,
+            staged_at,
+            retry_backoff,
+            unknown_fields: UnknownFields { text: fields },
+        },
+    ))
+}
+
+/// Sets `slot` to `value` unless it's already set, returning `true` if it was.
+fn set_once<T>(slot: &mut Option<T>, value: T) -> bool {
+    if slot.is_some() {
+        return true;
+    }
+    *slot = Some(value);
+    false
+}
+
+/// A single optional line of a `[passive]` component.
+enum PassiveField<'a> {
+    Name(Option<(Option<&'a str>, ImageLabel<'a>)>),
+    Version(Option<(u32, Option<ImageVersion>)>),
+    Hash([u8; 32]),
+    Channel(Channel),
+    MinHwrev(u32),
+    Status(Option<UpdateStatus<'a>>),
+    BootAttempts(u32),
+    TestWindow(u32),
+    DryRun(bool),
+    StagedAt(u64),
+    RetryBackoff(RetryBackoff),
+}
+
+/// Parses whichever optional passive field comes next. The fields may appear in any order
+/// after `ready_for_update_flag`.
+fn passive_field<'a, E: ConfigParseError<'a>>(
+    input: &'a str,
+    options: ParseOptions,
+) -> ParseResult<'a, PassiveField<'a>, E> {
+    line(alt((
+        map(
+            |i| passive_name_with_ext(i, options.image_extensions),
+            PassiveField::Name,
+        ),
+        map(passive_version, PassiveField::Version),
+        map(image_hash, PassiveField::Hash),
+        map(channel, PassiveField::Channel),
+        map(min_hwrev, PassiveField::MinHwrev),
+        map(
+            |i| update_status_with(i, options.lenient_status),
+            PassiveField::Status,
+        ),
+        map(boot_attempts, PassiveField::BootAttempts),
+        map(test_window_secs, PassiveField::TestWindow),
+        map(dry_run, PassiveField::DryRun),
+        map(staged_at, PassiveField::StagedAt),
+        map(retry_backoff, PassiveField::RetryBackoff),
+    )))(input)
+}
+
+/// The keys [`passive_config`] understands.
+const PASSIVE_KEYS: &[&str] = &[
+    READY_FOR_UPDATE_KEY,
+    IMAGE_NAME_KEY,
+    IMAGE_VERSION_KEY,
+    IMAGE_HASH_KEY,
+    CHANNEL_KEY,
+    MIN_HWREV_KEY,
+    UPDATE_STATUS_KEY,
+    BOOT_ATTEMPTS_KEY,
+    TEST_WINDOW_SECS_KEY,
+    DRY_RUN_KEY,
+    STAGED_AT_KEY,
+    RETRY_BACKOFF_KEY,
+];
+
+/// The keys only a `[passive]` component has, as opposed to the image fields it shares with
+/// the other sections.
+const PASSIVE_ONLY_KEYS: &[&str] = &[
+    READY_FOR_UPDATE_KEY,
+    UPDATE_STATUS_KEY,
+    BOOT_ATTEMPTS_KEY,
+    TEST_WINDOW_SECS_KEY,
+    DRY_RUN_KEY,
+    STAGED_AT_KEY,
+    RETRY_BACKOFF_KEY,
+];
+
+/// Returns the input starting at the next `key=value` line, if it's one of
+/// [`PASSIVE_ONLY_KEYS`] rather than a section header, i.e. a `[passive]` component has lost
+/// its header.
+fn passive_only_key(input: &str) -> Option<&str> {
+    let (line, _) = multispace_or_comment0::<ConfigError>(input).ok()?;
+    let (_, (key, _)) = raw_pair::<ConfigError>(line).ok()?;
+    PASSIVE_ONLY_KEYS.contains(&key).then_some(line)
+}
+
+/// Skips any `key=value` lines whose key isn't one of `known`, returning how many were
+/// skipped. This lets an older parser read a section written by a newer tool.
+fn unknown_keys<'a, E: ConfigParseError<'a>>(
+    known: &'static [&'static str],
+) -> impl FnMut(&'a str) -> ParseResult<'a, usize, E> {
+    many0_count(line(verify(raw_pair, move |(key, _): &(&str, &str)| {
+        !known.contains(key)
+    })))
+}
+
+#[cfg(test)]
+fn settings_config(input: &str) -> ParseResult<'_, SettingsConf> {
+    settings_config_with(input, &SectionNames::DEFAULT, ParseOptions::default())
+}
+
+/// Parses a `[signature]` section: its header, then an `alg=` and a `sig=` line. A `sig`
+/// that isn't padded base64 is a hard failure. See [`parse_signed`].
+pub fn signature_block(input: &str) -> ParseResult<'_, SignatureBlock<'_>> {
+    let (next_input, (_, alg, sig)) = tuple((
+        line(tag(SIGNATURE_HEADER)),
+        line(required(
+            ALG_KEY,
+            key_value(ALG_KEY, name_chars(&['-', '_'])),
+        )),
+        line(required(
+            SIG_KEY,
+            key_value(SIG_KEY, take_till(|c: char| c.is_whitespace() || c == '#')),
+        )),
+    ))(input)?;
+    if !is_base64(sig) {
+        return Err(Err::Failure(ConfigError::InvalidSignature(sig)));
+    }
+    Ok((next_input, SignatureBlock { alg, sig }))
+}
+
+/// The value of a standard base64 digit, if `b` is one.
+fn base64_digit(b: u8) -> Option<u8> {
+    match b {
+        b'A'..=b'Z' => Some(b - b'A'),
+        b'a'..=b'z' => Some(b - b'a' + 26),
+        b'0'..=b'9' => Some(b - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+/// Returns `true` if `text` is non-empty, padded base64, i.e. whole groups of 4 digits with
+/// at most two `=` at the very end.
+fn is_base64(text: &str) -> bool {
+    let digits = text.trim_end_matches('=');
+    !text.is_empty()
+        && text.len().is_multiple_of(4)
+        && text.len() - digits.len() <= 2
+        && digits.bytes().all(|b| base64_digit(b).is_some())
+}
+
+fn settings_config_with<'a, E: ConfigParseError<'a>>(
+    input: &'a str,
+    names: &SectionNames,
+    options: ParseOptions,
+) -> ParseResult<'a, SettingsConf, E> {
+    let (i, settings_config) = header_with(input, names, ConfigKeys::Settings)?;
+    let (i, watchdog_timeout) = opt(line(watchdog_timeout))(i)?;
+    let watchdog = watchdog_timeout.and(Some(WATCHDOG_TIMEOUT_KEY));
+    let (i, _) = repeated_fields(i, &[watchdog], options.duplicate_fields)?;
+    let (i, auto_rollback) = opt(line(auto_rollback))(i)?;
+    let seen = [watchdog, auto_rollback.and(Some(AUTO_ROLLBACK_KEY))];
+    let (i, _) = repeated_fields(i, &seen, options.duplicate_fields)?;
+    let (next_input, _) = multispace_or_comment0(i)?;
+    Ok((
+        next_input,
+        SettingsConf {
+            settings_config,
+            watchdog_timeout,
+            auto_rollback,
+        },
+    ))
+}
+
+/// A single `[board]`, `[active]`, `[passive]`, `[recovery]` or `[settings]` component of a
+/// config file, as yielded by [`ConfigStreamParser::feed`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Section<'a> {
+    /// A `[board]` component.
+    Board(BoardConf<'a>),
+    /// An `[active]` component.
+    Active(ActiveConf<'a>),
+    /// A `[passive]` component.
+    Passive(PassiveConf<'a>),
+    /// A `[recovery]` component.
+    Recovery(RecoveryConf<'a>),
+    /// A `[settings]` component.
+    Settings(SettingsConf),
+}
+
+/// Peeks at the header of the next section without consuming anything.
+fn section_key<'a, E: ConfigParseError<'a>>(
+    input: &'a str,
+    names: &SectionNames,
+) -> ParseResult<'a, ConfigKeys, E> {
+    peek(preceded(multispace_or_comment0, |i| {
+        config_keys_with(i, names)
+    }))(input)
+}
+
+/// Whichever section comes next. Errors get its standard header (ex: `"[passive]"`) as
+/// context, whatever `names` calls it.
+fn section<'a, E: ConfigParseError<'a>>(
+    input: &'a str,
+    names: &SectionNames,
+    options: ParseOptions,
+) -> ParseResult<'a, Section<'a>, E> {
+    let (_, key) = section_key(input, names)?;
+    context(key.header(), move |i| match key {
+        ConfigKeys::Board => map(|i| board_config_with(i, names, options), Section::Board)(i),
+        ConfigKeys::Active => map(|i| active_config_with(i, names, options), Section::Active)(i),
+        ConfigKeys::Passive => map(|i| passive_config_with(i, names, options), Section::Passive)(i),
+        ConfigKeys::Recovery => map(
+            |i| recovery_config_with(i, names, options),
+            Section::Recovery,
+        )(i),
+        ConfigKeys::Settings => map(
+            |i| settings_config_with(i, names, options),
+            Section::Settings,
+        )(i),
+    })(input)
+}
+
+/// The optional `version=<n>` line at the top of a config file. Files without one are
+/// [`DEFAULT_FORMAT_VERSION`]; versions this crate doesn't know are a hard failure.
+fn format_version<'a, E: ConfigParseError<'a>>(input: &'a str) -> ParseResult<'a, u32, E> {
+    let supported = |i| {
+        let (next_input, value) = digit1(i)?;
+        match u32::from_str(value) {
+            Ok(version @ 1..=FORMAT_VERSION) => Ok((next_input, version)),
+            _ => {
+                let error = ConfigError::UnsupportedFormatVersion(value);
+                Err(Err::Failure(config_error(i, error)))
+            }
+        }
+    };
+    let (next_input, version) = opt(line(key_value(FORMAT_VERSION_KEY, supported)))(input)?;
+    Ok((next_input, version.unwrap_or(DEFAULT_FORMAT_VERSION)))
+}
+
+fn config(input: &str) -> ParseResult<'_, Config<'_>> {
+    config_with(input, &SectionNames::DEFAULT, ParseOptions::default())
+}
+
+/// An optional leading board section, then an active and a passive section plus optional
+/// recovery and settings sections, in any order. With
+/// the `multi-slot` feature, up to `MAX_PASSIVE_SLOTS` passive sections are accepted.
+fn config_with<'a, E: ConfigParseError<'a>>(
+    input: &'a str,
+    names: &SectionNames,
+    options: ParseOptions,
+) -> ParseResult<'a, Config<'a>, E> {
+    let (mut remaining, format_version) = format_version(input)?;
+    let leading = remaining;
+    let mut board = None;
+    let (mut active, mut passive, mut recovery, mut settings) = (None, None, None, None);
+    #[cfg(feature = "multi-slot")]
+    let mut extra_passive = heapless::Vec::new();
+    let next_is = |input, key| section_key::<ConfigError>(input, names) == Ok((input, key));
+    while active.is_none()
+        || passive.is_none()
+        || (board.is_none() && next_is(remaining, ConfigKeys::Board))
+        || (recovery.is_none() && next_is(remaining, ConfigKeys::Recovery))
+        || (settings.is_none() && next_is(remaining, ConfigKeys::Settings))
+        || (cfg!(feature = "multi-slot") && next_is(remaining, ConfigKeys::Passive))
+    {
+        if passive.is_none() {
+            if let Some(orphan) = passive_only_key(remaining) {
+                let error = ConfigError::MissingSection(orphan);
+                return Err(Err::Failure(config_error(orphan, error)));
+            }
+        }
+        let (next_input, section) = section(remaining, names, options)?;
+        let duplicate = match section {
+            Section::Board(res) if remaining == leading => {
+                board = Some(res);
+                false
+            }
+            Section::Board(_) if board.is_none() => {
+                let (header, _) = multispace_or_comment0(remaining)?;
+                let error = ConfigError::UnknownSection(header);
+                return Err(Err::Failure(config_error(header, error)));
+            }
+            Section::Board(_) => true,
+            Section::Active(res) => active.replace(res).is_some(),
+            Section::Passive(res) if passive.is_none() => {
+                passive = Some(res);
+                false
+            }
+            #[cfg(feature = "multi-slot")]
+            Section::Passive(res) => extra_passive.push(res).is_err(),
+            #[cfg(not(feature = "multi-slot"))]
+            Section::Passive(_) => true,
+            Section::Recovery(res) => recovery.replace(res).is_some(),
+            Section::Settings(res) => settings.replace(res).is_some(),
+        };
+        if duplicate {
+            let (header, _) = multispace_or_comment0(remaining)?;
+            let error = ConfigError::DuplicateSection(header);
+            return Err(Err::Failure(config_error(header, error)));
+        }
+        remaining = next_input;
+    }
+    // the loop only exits once both sections are present, but don't panic over it
+    let (active, passive) = active
+        .zip(passive)
+        .ok_or_else(|| Err::Error(config_error(remaining, ConfigError::Incomplete)))?;
+    Ok((
+        remaining,
+        Config {
+            format_version,
+            board,
+            active,
+            passive,
+            recovery,
+            settings,
+            #[cfg(feature = "multi-slot")]
+            extra_passive,
+        },
+    ))
+}
+
+/// Parses the provided configuration file into a [`Config`]. A valid config file must
+/// contain an active and a passive component and may contain recovery and settings
+/// components, in any order, optionally preceded by a `version=<n>` line (see
+/// [`FORMAT_VERSION`]) and a `[board]` component, in that order. The passive component may
+/// contain optional fields such `image_name`, `image_version` and `update_status`, in any
+/// order after `ready_for_update_flag`
+///
+/// The whole input must be consumed; anything other than whitespace or comments after the
+/// config is reported as [`ConfigError::TrailingInput`]. A component that appears twice is
+/// reported as [`ConfigError::DuplicateSection`], except that with the `multi-slot` feature
+/// up to `MAX_PASSIVE_SLOTS` passive components are collected (see
+/// [`Config::passive_slots`]). Keys that only a passive component has, found before any
+/// `[passive]` header, are reported as [`ConfigError::MissingSection`].
+///
+/// [`parse`] never panics: any input yields either a [`Config`] or a [`ConfigError`].
+///
+/// **note:** for an example of what constitutes a `valid config file`, please see `update_conf.txt`
+pub fn parse(input: &str) -> Result<Config<'_>, ConfigError<'_>> {
+    parse_complete(input, config)
+}
+
+/// Runs `parser` on `input`, reporting anything other than whitespace or comments left
+/// over afterwards as [`ConfigError::TrailingInput`].
+fn parse_complete<'a, O>(
+    input: &'a str,
+    mut parser: impl FnMut(&'a str) -> ParseResult<'a, O>,
+) -> Result<O, ConfigError<'a>> {
+    let (remaining, res) = parser(input)?;
+    match multispace_or_comment0::<ConfigError>(remaining) {
+        Ok(("", _)) => Ok(res),
+        _ => Err(ConfigError::TrailingInput(remaining)),
+    }
+}
+
+/// Parses the provided configuration file and returns the active and passive components
+/// as a tuple. A valid config file must contain an active and a passive component.
+/// [`parse_config`] assumes the provided config (always) includes the active and
+/// passive components. The passive componets may contain optional fields such `image_name`,
+/// `image_version` and `update_status`
+///
+/// [`parse_config`] never panics: any input yields either the parsed components or an error.
+///
+/// **note:** for an example of what constitutes a `valid config file`, please see `update_conf.txt`
+pub fn parse_config(input: &str) -> IResult<&str, (ActiveConf<'_>, PassiveConf<'_>)> {
+    parse_config_with(input, &SectionNames::DEFAULT)
+}
+
+/// Parses a lone `[active]` component, ex: one read from its own flash region. Like
+/// [`parse_config`], trailing input is returned rather than rejected.
+pub fn parse_active(input: &str) -> IResult<&str, ActiveConf<'_>> {
+    active_config(input).map_err(|e| e.map(|e| e.into_nom_error(input)))
+}
+
+/// Parses a lone `[passive]` component, ex: one read from its own flash region. Like
+/// [`parse_config`], trailing input is returned rather than rejected.
+pub fn parse_passive(input: &str) -> IResult<&str, PassiveConf<'_>> {
+    passive_config(input).map_err(|e| e.map(|e| e.into_nom_error(input)))
+}
+
+/// Parses a signed config: a config as accepted by [`parse`], followed by a `[signature]`
+/// section (see [`signature_block`]). Also returns the signed text, i.e. everything before
+/// the `[signature]` header (see [`signed_region`]), for the caller to check the signature
+/// against.
+pub fn parse_signed(
+    input: &str,
+) -> Result<(Config<'_>, SignatureBlock<'_>, &str), ConfigError<'_>> {
+    let (remaining, config) = config(input)?;
+    let (signature, _) = multispace_or_comment0::<ConfigError>(remaining)?;
+    let signed = &input[..input.len() - signature.len()];
+    let signature = parse_complete(signature, signature_block)?;
+    Ok((config, signature, signed))
+}
+
+/// Returns the part of a signed config the signature covers: everything up to (but not
+/// including) its last [`SIGNATURE_HEADER`], without parsing anything. `None` if there's no
+/// `[signature]` section.
+pub fn signed_region(input: &str) -> Option<&str> {
+    header_lines(input)
+        .map(|start| input.len() - input[start..].trim_start_matches([' ', '\t']).len())
+        .filter(|header| {
+            terminated(tag(SIGNATURE_HEADER), end_of_line::<ConfigError>)(&input[*header..]).is_ok()
+        })
+        .last()
+        .map(|header| &input[..header])
+}
+
+/// Like [`parse`], but with the given policy for a field that appears more than once in a
+/// section. [`parse`] itself always uses [`DuplicateFields::Reject`].
+pub fn parse_with_duplicates(
+    input: &str,
+    policy: DuplicateFields,
+) -> Result<Config<'_>, ConfigError<'_>> {
+    let options = ParseOptions {
+        duplicate_fields: policy,
+        ..ParseOptions::default()
+    };
+    parse_with_options(input, options)
+}
+
+/// Like [`parse`], but with the leniency given by `options`. With the default options this
+/// is the same as [`parse`].
+pub fn parse_with_options<'a>(
+    input: &'a str,
+    options: ParseOptions,
+) -> Result<Config<'a>, ConfigError<'a>> {
+    parse_complete(input, |i| config_with(i, &SectionNames::DEFAULT, options))
+}
+
+/// Like [`parse`], but returns only the active component, ex: for a bootloader that never
+/// looks at the staged update. The whole file must still be a valid config.
+pub fn parse_active_only(input: &str) -> Result<ActiveConf<'_>, ConfigError<'_>> {
+    parse(input).map(|config| config.active)
+}
+
+/// Like [`parse`], but returns only the (first) passive component, ex: for an updater that
+/// only stages images. The whole file must still be a valid config.
+pub fn parse_passive_only(input: &str) -> Result<PassiveConf<'_>, ConfigError<'_>> {
+    parse(input).map(|config| config.passive)
+}
+
+/// Parses a batch file holding several configs, one per record, with records separated by a
+/// [`RECORD_SEPARATOR`] line (surrounding whitespace is ignored). Each record is parsed like
+/// [`parse`] and yielded in order, so a bad record doesn't stop the ones after it. Records
+/// holding only whitespace, ex: after a trailing separator, are skipped, and the iterator
+/// ends with the input.
+pub fn parse_many(input: &str) -> impl Iterator<Item = Result<Config<'_>, ConfigError<'_>>> {
+    let mut remaining = input;
+    core::iter::from_fn(move || loop {
+        if remaining.is_empty() {
+            return None;
+        }
+        let mut end = remaining.len();
+        let mut next = "";
+        let mut offset = 0;
+        for line in remaining.split_inclusive('\n') {
+            if line.trim() == RECORD_SEPARATOR {
+                end = offset;
+                next = &remaining[offset + line.len()..];
+                break;
+            }
+            offset += line.len();
+        }
+        let record = &remaining[..end];
+        remaining = next;
+        if !record.trim().is_empty() {
+            return Some(parse(record));
+        }
+    })
+}
+
+/// Like [`parse_config`], but also returns a [`ParseReport`] of how much of `input` was
+/// parsed.
+pub fn parse_config_reported(
+    input: &str,
+) -> IResult<&str, (ActiveConf<'_>, PassiveConf<'_>, ParseReport)> {
+    config(input)
+        .map(|(next_input, config)| {
+            let report = ParseReport {
+                sections: 2
+                    + usize::from(config.board.is_some())
+                    + usize::from(config.recovery.is_some())
+                    + usize::from(config.settings.is_some())
+                    + config.extra_passive().len(),
+                bytes_consumed: input.len() - next_input.len(),
+            };
+            (next_input, (config.active, config.passive, report))
+        })
+        .map_err(|e| e.map(|e| e.into_nom_error(input)))
+}
+
+/// Like [`parse_config`], but with the section headers named as in `names`. Note that
+/// the `Display` impls always emit the standard names.
+pub fn parse_config_with<'a>(
+    input: &'a str,
+    names: &SectionNames,
+) -> IResult<&'a str, (ActiveConf<'a>, PassiveConf<'a>)> {
+    config_with::<ConfigError>(input, names, ParseOptions::default())
+        .map(|(next_input, config)| (next_input, (config.active, config.passive)))
+        .map_err(|e| e.map(|e| e.into_nom_error(input)))
+}
+
+/// Like [`parse_config`], but with an error type of the caller's choosing, ex: nom's
+/// `VerboseError` for a trace that `convert_error` can print. The grammar runs with `E`
+/// throughout, so `E` sees every step that led to a failure: the crate's own failures reach
+/// it as a [`ConfigError`] through `FromExternalError`, at the position they occurred, and
+/// each section and field adds its header or key (ex: `"[passive]"`, `"image_version"`) as
+/// context. With `E` as nom's `Error`, this returns the same as [`parse_config`].
+pub fn parse_config_as<'a, E>(
+    input: &'a str,
+) -> IResult<&'a str, (ActiveConf<'a>, PassiveConf<'a>), E>
+where
+    E: ParseError<&'a str> + ContextError<&'a str> + FromExternalError<&'a str, ConfigError<'a>>,
+{
+    config_with::<E>(input, &SectionNames::DEFAULT, ParseOptions::default())
+        .map(|(next_input, config)| (next_input, (config.active, config.passive)))
+}
+
+/// Like [`parse_config`], but requires the whole input to be consumed. Anything other
+/// than whitespace or comments after the config is reported as
+/// [`ConfigError::TrailingInput`], so partially-valid files are rejected.
+pub fn parse_config_strict(
+    input: &str,
+) -> Result<(ActiveConf<'_>, PassiveConf<'_>), ConfigError<'_>> {
+    parse(input).map(|config| (config.active, config.passive))
+}
+
+/// Like [`parse`], but refuses inputs longer than `max_len` bytes with
+/// [`ConfigError::InputTooLarge`] before looking at them. Useful where a config is never
+/// expected to be more than a few hundred bytes.
+pub fn parse_config_bounded(input: &str, max_len: usize) -> Result<Config<'_>, ConfigError<'_>> {
+    if input.len() > max_len {
+        return Err(ConfigError::InputTooLarge);
+    }
+    parse(input)
+}
+
+/// Like [`parse`], but keeps only the scalar fields of the config. See [`ConfigScalars`].
+pub fn parse_scalars(input: &str) -> Result<ConfigScalars, ConfigError<'_>> {
+    parse(input).map(|config| ConfigScalars {
+        active_version: config.active.image_version,
+        passive_flag: config.passive.ready_for_update_flag,
+        passive_version: config.passive.image_version,
+        // `parse` is strict, so this never drops an `Unknown`
+        passive_status: config
+            .passive
+            .update_status
+            .and_then(|status| status.known()),
+    })
+}
+
+// `FromStr` can't be implemented for these types: they borrow from the input, and
+// `FromStr::from_str` gives no way to tie the result's lifetime to the string it parsed.
+// `TryFrom<&str>` can, so `Config::try_from(text)?` is the strict, idiomatic spelling.
+// `OwnedConfig` borrows nothing, so it gets `FromStr` too.
+
+impl<'a> TryFrom<&'a str> for Config<'a> {
+    type Error = ConfigError<'a>;
+
+    /// Same as [`parse`].
+    fn try_from(i: &'a str) -> Result<Self, Self::Error> {
+        parse(i)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl FromStr for OwnedConfig {
+    type Err = OwnedConfigError;
+
+    /// Same as [`parse`] followed by [`Config::into_owned`], so `text.parse::<OwnedConfig>()`
+    /// is as strict as [`parse`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse(s)
+            .map(Config::into_owned)
+            .map_err(|error| LocatedError::new(s, error).into())
+    }
+}
+
+impl<'a> TryFrom<&'a str> for ActiveConf<'a> {
+    type Error = ConfigError<'a>;
+
+    /// Parses a lone `[active]` section, which must make up the whole input.
+    fn try_from(i: &'a str) -> Result<Self, Self::Error> {
+        parse_complete(i, active_config)
+    }
+}
+
+impl<'a> TryFrom<&'a str> for PassiveConf<'a> {
+    type Error = ConfigError<'a>;
+
+    /// Parses a lone `[passive]` section, which must make up the whole input.
+    fn try_from(i: &'a str) -> Result<Self, Self::Error> {
+        parse_complete(i, passive_config)
+    }
+}
+
+/// Assembles a config file that arrives in chunks, ex: one flash page at a time, in a
+/// buffer of `N` bytes. The parsed config borrows from that buffer, so `N` must fit the
+/// whole file. Only complete lines are looked at, so a keyword split across two chunks is
+/// never seen half-way, and each section is checked as soon as the header of the next one
+/// arrives rather than when the whole file is in. The `version=` line and comments before
+/// the first section are checked once its header arrives.
+pub struct ConfigStreamParser<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    // offset of the first section that hasn't been checked yet
+    checked: usize,
+    // from the `version=` line, once the first section header has arrived
+    format_version: Option<u32>,
+}
+
+impl<const N: usize> ConfigStreamParser<N> {
+    /// Creates a parser with an empty buffer.
+    pub fn new() -> Self {
+        ConfigStreamParser {
+            buf: [0; N],
+            len: 0,
+            checked: 0,
+            format_version: None,
+        }
+    }
+
+    /// Appends `chunk` to the buffered input and checks any sections it completes, returning
+    /// them. Fails with [`ConfigError::BufferFull`] if `chunk` doesn't fit.
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<CompletedSections<'_>, ConfigError<'_>> {
+        let end = self
+            .len
+            .checked_add(chunk.len())
+            .filter(|end| *end <= N)
+            .ok_or(ConfigError::BufferFull)?;
+        self.buf[self.len..end].copy_from_slice(chunk);
+        self.len = end;
+
+        let none = CompletedSections { lines: "" };
+        let lines_end = match self.buf[..self.len].iter().rposition(|b| *b == b'\n') {
+            Some(i) => i + 1,
+            None => return Ok(none),
+        };
+        let lines = core::str::from_utf8(&self.buf[self.checked..lines_end])
+            .map_err(|_| ConfigError::InvalidUtf8)?;
+        let mut start = 0;
+        if self.format_version.is_none() {
+            let Some(header) = header_lines(lines).next() else {
+                return Ok(none);
+            };
+            self.format_version = Some(parse_complete(&lines[..header], format_version)?);
+            start = header;
+        }
+        let first = start;
+        while let Some(len) = section_len(&lines[start..]) {
+            parse_complete(&lines[start..start + len], |i| {
+                section(i, &SectionNames::DEFAULT, ParseOptions::default())
+            })?;
+            start += len;
+        }
+        self.checked += start;
+        Ok(CompletedSections {
+            lines: &lines[first..start],
+        })
+    }
+
+    /// Returns the format version from the input's `version=` line, or
+    /// [`DEFAULT_FORMAT_VERSION`] without one, once the first section header has been fed.
+    pub fn format_version(&self) -> Option<u32> {
+        self.format_version
+    }
+
+    /// Parses the buffered input as a whole, like [`parse_config_strict`].
+    pub fn finish(&self) -> Result<(ActiveConf<'_>, PassiveConf<'_>), ConfigError<'_>> {
+        let input =
+            core::str::from_utf8(&self.buf[..self.len]).map_err(|_| ConfigError::InvalidUtf8)?;
+        parse_config_strict(input)
+    }
+}
+
+impl<const N: usize> Default for ConfigStreamParser<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The sections completed by one [`ConfigStreamParser::feed`] call, in input order. They
+/// borrow the parser's buffer, so they must be dropped before the next chunk is fed.
+#[derive(Debug, Clone)]
+pub struct CompletedSections<'a> {
+    // the text of the completed sections, each already checked by `feed`
+    lines: &'a str,
+}
+
+impl<'a> Iterator for CompletedSections<'a> {
+    type Item = Section<'a>;
+
+    fn next(&mut self) -> Option<Section<'a>> {
+        let len = section_len(self.lines).unwrap_or(self.lines.len());
+        let (_, section) = section::<ConfigError>(
+            &self.lines[..len],
+            &SectionNames::DEFAULT,
+            ParseOptions::default(),
+        )
+        .ok()?;
+        self.lines = &self.lines[len..];
+        Some(section)
+    }
+}
+
+/// Returns the length of the first section in `lines` if it's followed by the header of
+/// another section, i.e. if the first section is known to be complete.
+fn section_len(lines: &str) -> Option<usize> {
+    header_lines(lines).nth(1)
+}
+
+/// The offsets of the lines in `lines` that start (after indentation) a section header.
+fn header_lines(lines: &str) -> impl Iterator<Item = usize> + '_ {
+    core::iter::once(0)
+        .chain(lines.match_indices('\n').map(|(i, _)| i + 1))
+        .filter(|i| lines[*i..].trim_start_matches([' ', '\t']).starts_with('['))
+}
+
+/// The byte ranges of the values of a config's fields, as returned by [`parse_config_spans`].
+/// Offsets are relative to the start of the original input, so a value can be rewritten in
+/// place with `buf[span].copy_from_slice(new_value)` (as long as it keeps its length). A
+/// field that isn't in the input has no span. The passive fields are those of the first
+/// `[passive]` section, the one [`parse_config_spans`] returns.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FieldSpans {
+    /// The active section's `image_name` value.
+    pub active_image_name: Range<usize>,
+    /// The active section's `image_version` value.
+    pub active_image_version: Range<usize>,
+    /// The active section's `image_hash` value.
+    pub active_image_hash: Option<Range<usize>>,
+    /// The active section's `channel` value.
+    pub active_channel: Option<Range<usize>>,
+    /// The active section's `min_hwrev` value.
+    pub active_min_hwrev: Option<Range<usize>>,
+    /// The passive section's `ready_for_update_flag` value.
+    pub ready_for_update_flag: Range<usize>,
+    /// The passive section's `image_name` value.
+    pub passive_image_name: Option<Range<usize>>,
+    /// The passive section's `image_version` value.
+    pub passive_image_version: Option<Range<usize>>,
+    /// The passive section's `image_hash` value.
+    pub passive_image_hash: Option<Range<usize>>,
+    /// The passive section's `channel` value.
+    pub passive_channel: Option<Range<usize>>,
+    /// The passive section's `min_hwrev` value.
+    pub passive_min_hwrev: Option<Range<usize>>,
+    /// The passive section's `update_status` value.
+    pub update_status: Option<Range<usize>>,
+    /// The passive section's `boot_attempts` value.
+    pub boot_attempts: Option<Range<usize>>,
+    /// The passive section's `test_window_secs` value.
+    pub test_window_secs: Option<Range<usize>>,
+    /// The passive section's `dry_run` value.
+    pub dry_run: Option<Range<usize>>,
+    /// The passive section's `staged_at` value.
+    pub staged_at: Option<Range<usize>>,
+    /// The passive section's `retry_backoff_secs` value.
+    pub retry_backoff: Option<Range<usize>>,
+}
+
+/// Like [`parse_config_strict`], but also returns where each field's value sits in `input`.
+pub fn parse_config_spans(
+    input: &str,
+) -> Result<(ActiveConf<'_>, PassiveConf<'_>, FieldSpans), ConfigError<'_>> {
+    let (active, passive) = parse_config_strict(input)?;
+    let mut spans = FieldSpans::default();
+    let mut passive_seen = false;
+    for start in header_lines(input) {
+        let Ok((_, key)) = section_key::<ConfigError>(&input[start..], &SectionNames::DEFAULT)
+        else {
+            continue;
+        };
+        if key == ConfigKeys::Passive {
+            // only the first slot, the one that's returned
+            if passive_seen {
+                continue;
+            }
+            passive_seen = true;
+        }
+        for (name, value) in section_pairs(&input[start..]) {
+            let offset = offset_in(input, value);
+            let span = offset..offset + value.len();
+            match (&key, name) {
+                (ConfigKeys::Active, IMAGE_NAME_KEY) => spans.active_image_name = span,
+                (ConfigKeys::Active, IMAGE_VERSION_KEY) => spans.active_image_version = span,
+                (ConfigKeys::Active, IMAGE_HASH_KEY) => spans.active_image_hash = Some(span),
+                (ConfigKeys::Active, CHANNEL_KEY) => spans.active_channel = Some(span),
+                (ConfigKeys::Active, MIN_HWREV_KEY) => spans.active_min_hwrev = Some(span),
+                (ConfigKeys::Passive, READY_FOR_UPDATE_KEY) => spans.ready_for_update_flag = span,
+                (ConfigKeys::Passive, IMAGE_NAME_KEY) => spans.passive_image_name = Some(span),
+                (ConfigKeys::Passive, IMAGE_VERSION_KEY) => {
+                    spans.passive_image_version = Some(span)
+                }
+                (ConfigKeys::Passive, IMAGE_HASH_KEY) => spans.passive_image_hash = Some(span),
+                (ConfigKeys::Passive, CHANNEL_KEY) => spans.passive_channel = Some(span),
+                (ConfigKeys::Passive, MIN_HWREV_KEY) => spans.passive_min_hwrev = Some(span),
+                (ConfigKeys::Passive, UPDATE_STATUS_KEY) => spans.update_status = Some(span),
+                (ConfigKeys::Passive, BOOT_ATTEMPTS_KEY) => spans.boot_attempts = Some(span),
+                (ConfigKeys::Passive, TEST_WINDOW_SECS_KEY) => spans.test_window_secs = Some(span),
+                (ConfigKeys::Passive, DRY_RUN_KEY) => spans.dry_run = Some(span),
+                (ConfigKeys::Passive, STAGED_AT_KEY) => spans.staged_at = Some(span),
+                (ConfigKeys::Passive, RETRY_BACKOFF_KEY) => spans.retry_backoff = Some(span),
+                _ => {}
+            }
+        }
+    }
+    Ok((active, passive, spans))
+}
+
+/// The exact text of each section of a config, header included, as returned by
+/// [`parse_config_with_raw`]. A section runs from its header up to and including the line
+/// ending of its last non-blank line, so blank lines between sections belong to neither. A
+/// section that isn't in the input has no text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RawSections<'a> {
+    /// The `[board]` section.
+    pub board: Option<&'a str>,
+    /// The `[active]` section.
+    pub active: &'a str,
+    /// The (first) `[passive]` section.
+    pub passive: &'a str,
+    /// The `[recovery]` section.
+    pub recovery: Option<&'a str>,
+    /// The `[settings]` section.
+    pub settings: Option<&'a str>,
+}
+
+/// Like [`parse`], but also returns the original text of each section, ex: to re-sign only
+/// the section that changed without round-tripping it through `Display`.
+pub fn parse_config_with_raw(
+    input: &str,
+) -> Result<(Config<'_>, RawSections<'_>), ConfigError<'_>> {
+    let config = parse(input)?;
+    let mut raw = RawSections::default();
+    for start in header_lines(input) {
+        let header = input[start..].trim_start_matches([' ', '\t']);
+        let Ok((next_input, section)) =
+            section::<ConfigError>(header, &SectionNames::DEFAULT, ParseOptions::default())
+        else {
+            continue;
+        };
+        let text = header[..header.len() - next_input.len()].trim_end();
+        let ending =
+            line_ending_or_eof::<ConfigError>(&header[text.len()..]).map_or(0, |(_, e)| e.len());
+        let text = &header[..text.len() + ending];
+        match section {
+            Section::Board(_) => raw.board = Some(text),
+            Section::Active(_) => raw.active = text,
+            Section::Passive(_) if raw.passive.is_empty() => raw.passive = text,
+            Section::Passive(_) => {}
+            Section::Recovery(_) => raw.recovery = Some(text),
+            Section::Settings(_) => raw.settings = Some(text),
+        }
+    }
+    Ok((config, raw))
+}
+
+/// The raw `(key, value)` pairs of up to `N` unknown keys, as returned by
+/// [`parse_with_extra_keys`].
+#[cfg(feature = "heapless")]
+pub type ExtraKeys<'a, const N: usize> = heapless::Vec<(&'a str, &'a str), N>;
+
+/// Like [`parse`], but also collects the `(key, value)` pairs of the unknown keys it skipped
+/// (see [`PassiveConf::unknown_keys`]), in the order they appear, ex: to pass fields written
+/// by a newer tool along without `alloc`. Fails with [`ConfigError::TooManyExtraKeys`] if
+/// there are more than `N`.
+#[cfg(feature = "heapless")]
+pub fn parse_with_extra_keys<const N: usize>(
+    input: &str,
+) -> Result<(Config<'_>, ExtraKeys<'_, N>), ConfigError<'_>> {
+    let config = parse(input)?;
+    let mut extra_keys = heapless::Vec::new();
+    for start in header_lines(input) {
+        if section_key::<ConfigError>(&input[start..], &SectionNames::DEFAULT)
+            != Ok((&input[start..], ConfigKeys::Passive))
+        {
+            continue;
+        }
+        for pair in section_pairs(&input[start..]).filter(|(key, _)| !PASSIVE_KEYS.contains(key)) {
+            extra_keys
+                .push(pair)
+                .map_err(|_| ConfigError::TooManyExtraKeys)?;
+        }
+    }
+    Ok((config, extra_keys))
+}
+
+/// Like [`parse_config`], but reads raw bytes, ex: straight off flash. The grammar is
+/// ASCII-only, so there's no need to validate the whole input as UTF-8 first: the config
+/// is parsed from the longest valid UTF-8 prefix, and anything after it (such as erased
+/// `0xff` flash) is left in the remainder.
+pub fn parse_config_bytes(input: &[u8]) -> IResult<&[u8], (ActiveConf<'_>, PassiveConf<'_>)> {
+    let text = match core::str::from_utf8(input) {
+        Ok(text) => text,
+        Err(e) => core::str::from_utf8(&input[..e.valid_up_to()]).unwrap_or_default(),
+    };
+    let to_bytes = |slice: &str| &input[offset_in(text, slice)..];
+    parse_config(text)
+        .map(|(next_input, res)| (to_bytes(next_input), res))
+        .map_err(|e| e.map(|e| Error::new(to_bytes(e.input), e.code)))
+}
+
+/// Like [`parse`], but reads raw bytes as they come off flash: a leading UTF-8 byte order
+/// mark is skipped, and trailing `0x00`/`0xff` padding (ex: the erase pattern) and whitespace
+/// are trimmed. What's left must be valid UTF-8 ([`ConfigError::InvalidUtf8`]) and a complete
+/// config.
+pub fn parse_config_lenient(input: &[u8]) -> Result<Config<'_>, ConfigError<'_>> {
+    let input = input.strip_prefix(b"\xef\xbb\xbf").unwrap_or(input);
+    let end = input
+        .iter()
+        .rposition(|b| !matches!(b, 0x00 | 0xff) && !b.is_ascii_whitespace())
+        .map_or(0, |i| i + 1);
+    let text = core::str::from_utf8(&input[..end]).map_err(|_| ConfigError::InvalidUtf8)?;
+    parse(text)
+}
+
+/// Like [`parse_config_strict`], but reports the line and column at which parsing failed,
+/// ex: to point a user at the offending line of a hand-edited file.
+pub fn parse_config_located(
+    input: &str,
+) -> Result<(ActiveConf<'_>, PassiveConf<'_>), LocatedError<'_>> {
+    parse_config_strict(input).map_err(|error| LocatedError::new(input, error))
+}
+
+/// Like [`parse`], but instead of stopping at the first bad line, checks every field line
+/// on its own and returns all the problems found, ex: to list them in an editor. A line in an
+/// unknown section is skipped after reporting the section once. If every line is fine but the
+/// config as a whole isn't (ex: a section or mandatory field is missing), that structural
+/// error is the only one returned. The [`Config`] is only returned if there are no errors.
+#[cfg(feature = "alloc")]
+pub fn parse_config_collect_errors(input: &str) -> (Option<Config<'_>>, Vec<ConfigError<'_>>) {
+    let mut errors = Vec::new();
+    let mut section = None;
+    for text in input.split_inclusive('\n') {
+        let text = text.trim_start_matches([' ', '\t']);
+        if multispace_or_comment0::<ConfigError>(text).is_ok_and(|(rest, _)| rest.is_empty()) {
+            continue;
+        }
+        if text.starts_with('[') {
+            section = match config_keys_with::<ConfigError>(text, &SectionNames::DEFAULT) {
+                Ok((_, key)) => Some(key),
+                Err(e) => {
+                    errors.push(e.into());
+                    None
+                }
+            };
+            continue;
+        }
+        if let Some(key) = section {
+            if let Err(e) = check_line(key, text) {
+                errors.push(e);
+            }
+        }
+    }
+    match parse(input) {
+        Ok(config) if errors.is_empty() => (Some(config), errors),
+        Ok(_) => (None, errors),
+        Err(error) => {
+            if errors.is_empty() {
+                errors.push(error);
+            }
+            (None, errors)
+        }
+    }
+}
+
+/// Checks a single `key=value` line of the `section` component with the parser for its
+/// field. Keys the section doesn't know are accepted.
+#[cfg(feature = "alloc")]
+fn check_line(section: ConfigKeys, text: &str) -> Result<(), ConfigError<'_>> {
+    fn check<'a, O>(
+        parser: impl FnMut(&'a str) -> ParseResult<'a, O>,
+        text: &'a str,
+    ) -> Result<(), ConfigError<'a>> {
+        terminated(parser, end_of_line)(text)
+            .map(|_| ())
+            .map_err(ConfigError::from)
+    }
+    let (_, (key, _)) = raw_pair::<ConfigError>(text)?;
+    match (section, key) {
+        (ConfigKeys::Passive, IMAGE_NAME_KEY) => check(passive_name, text),
+        (ConfigKeys::Passive, IMAGE_VERSION_KEY) => check(passive_version, text),
+        (_, IMAGE_NAME_KEY) => check(image_path, text),
+        (_, IMAGE_VERSION_KEY) => check(any_version, text),
+        (_, IMAGE_HASH_KEY) => check(image_hash, text),
+        (ConfigKeys::Active | ConfigKeys::Passive, CHANNEL_KEY) => check(channel, text),
+        (ConfigKeys::Active | ConfigKeys::Passive | ConfigKeys::Recovery, MIN_HWREV_KEY) => {
+            check(min_hwrev, text)
+        }
+        (ConfigKeys::Passive, READY_FOR_UPDATE_KEY) => check(ready_for_update, text),
+        (ConfigKeys::Passive, UPDATE_STATUS_KEY) => check(update_status, text),
+        (ConfigKeys::Passive, BOOT_ATTEMPTS_KEY) => check(boot_attempts, text),
+        (ConfigKeys::Passive, TEST_WINDOW_SECS_KEY) => check(test_window_secs, text),
+        (ConfigKeys::Passive, DRY_RUN_KEY) => check(dry_run, text),
+        (ConfigKeys::Passive, STAGED_AT_KEY) => check(staged_at, text),
+        (ConfigKeys::Passive, RETRY_BACKOFF_KEY) => check(retry_backoff, text),
+        (ConfigKeys::Board, MODEL_KEY) => check(board_model, text),
+        (ConfigKeys::Board, HWREV_KEY) => check(board_hwrev, text),
+        (ConfigKeys::Settings, WATCHDOG_TIMEOUT_KEY) => check(watchdog_timeout, text),
+        (ConfigKeys::Settings, AUTO_ROLLBACK_KEY) => check(auto_rollback, text),
+        _ => Ok(()),
+    }
+}
+
+/// Iterates over the raw `(key, value)` pairs of a section, ex: to log a config written by
+/// a newer tool whose keys the typed parsers don't know. `input` may start with the section
+/// header; iteration stops at the next header or the end of input. Blank lines and comments
+/// are skipped, as are lines that aren't `key=value` pairs.
+pub fn section_pairs(input: &str) -> impl Iterator<Item = (&str, &str)> {
+    let mut remaining =
+        line(section_header::<ConfigError>)(input).map_or(input, |(next_input, _)| next_input);
+    core::iter::from_fn(move || loop {
+        let (next_input, _) = multispace_or_comment0::<ConfigError>(remaining).ok()?;
+        if next_input.is_empty() || section_header::<ConfigError>(next_input).is_ok() {
+            return None;
+        }
+        match terminated(raw_pair::<ConfigError>, end_of_line)(next_input) {
+            Ok((next_input, pair)) => {
+                remaining = next_input;
+                return Some(pair);
+            }
+            Err(_) => {
+                let (after_line, _) = not_line_ending::<_, ConfigError>(next_input).ok()?;
+                remaining = after_line;
+            }
+        }
+    })
+}
+
+/// Returns the raw value of `key` in a section, as [`section_pairs`] sees it, without
+/// parsing the section into its typed struct. Keys must match exactly, so `image` doesn't
+/// find `image_name`. If `key` appears more than once, the first value is returned.
+pub fn get_raw_value<'a>(section: &'a str, key: &str) -> Option<&'a str> {
+    section_pairs(section).find_map(|(name, value)| (name == key).then_some(value))
+}
+
+/// Any `key=value` pair, with the value running up to a comment or the end of the line.
+fn raw_pair<'a, E: ConfigParseError<'a>>(input: &'a str) -> ParseResult<'a, (&'a str, &'a str), E> {
+    separated_pair(
+        name_chars(&['_', '-']),
+        tuple((space0, char('='), space0)),
+        raw_value,
+    )(input)
+}
+
+/// The raw text of a value, up to a comment or the end of the line, without trailing
+/// spaces. A `#` inside a quoted value (ex: `"foo#bar.itb"`) doesn't start a comment, and
+/// the quotes are kept.
+fn raw_value<'a, E: ConfigParseError<'a>>(input: &'a str) -> ParseResult<'a, &'a str, E> {
+    let quoted = input
+        .strip_prefix('"')
+        .and_then(closing_quote)
+        .map_or(0, |i| i + 2);
+    let end = input[quoted..]
+        .find(['\r', '\n', '#'])
+        .map_or(input.len(), |i| quoted + i);
+    Ok((&input[end..], input[..end].trim_end()))
+}
+
+/// A non-empty run of ASCII alphanumerics and any of the `extra` characters.
+fn name_chars<'a, E: ConfigParseError<'a>>(
+    extra: &'static [char],
+) -> impl Fn(&'a str) -> ParseResult<'a, &'a str, E> {
+    move |input: &'a str| {
+        input.split_at_position1_complete(
+            |c| !c.is_ascii_alphanumeric() && !extra.contains(&c),
+            ErrorKind::AlphaNumeric,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libc_print::libc_println;
+
+    extern crate alloc;
+    use alloc::format;
+
+    #[test]
+    fn test_config_keys() {
+        assert_eq!(config_keys("[active]"), Ok(("", ConfigKeys::Active)));
+        assert_eq!(config_keys("[passive]"), Ok(("", ConfigKeys::Passive)));
+        assert_eq!(config_keys("[settings]"), Ok(("", ConfigKeys::Settings)));
+        assert_eq!(
+            config_keys("[passive]remaining"),
+            Ok(("remaining", ConfigKeys::Passive))
+        );
+        assert_eq!(
+            config_keys("prefix[passive]remaining"),
+            Err(Err::Error(ConfigError::Parse(Error::new(
+                "prefix[passive]remaining",
+                ErrorKind::Tag
+            ))))
+        );
+        assert_eq!(
+            config_keys("active"),
+            Err(Err::Error(ConfigError::Parse(Error::new(
+                "active",
+                ErrorKind::Tag
+            ))))
+        );
+        assert_eq!(
+            config_keys("active]"),
+            Err(Err::Error(ConfigError::Parse(Error::new(
+                "active]",
+                ErrorKind::Tag
+            ))))
+        );
+        assert_eq!(
+            config_keys("[]"),
+            Err(Err::Error(ConfigError::UnknownSection("[]")))
+        );
+        assert_eq!(
+            config_keys("[unknown]"),
+            Err(Err::Error(ConfigError::UnknownSection("[unknown]")))
+        );
+    }
+
+    #[test]
+    fn test_config_keys_try_from() {
+        assert_eq!(ConfigKeys::try_from("[active]"), Ok(ConfigKeys::Active));
+        assert_eq!(ConfigKeys::try_from("[passive]"), Ok(ConfigKeys::Passive));
+        for header in ["[active]", "[passive]", "[recovery]", "[settings]"] {
+            let key = ConfigKeys::try_from(header).unwrap();
+            let text: &'static str = key.into();
+            assert_eq!(text, header);
+            assert_eq!(ConfigKeys::try_from(text), Ok(key));
+        }
+        assert_eq!(
+            ConfigKeys::try_from("[unknown]"),
+            Err(ConfigError::UnknownSection("[unknown]"))
+        );
+    }
+
+    #[test]
+    fn test_image_name() {
+        assert_eq!(
+            image_name("image_name=signed-apertis-rpi4.itbblah"),
+            Ok(("blah", ("signed-apertis-rpi4", ".itb")))
+        );
+        assert_eq!(
+            image_name("image_name="),
+            Err(Err::Error(ConfigError::Parse(Error::new(
+                "",
+                ErrorKind::AlphaNumeric
+            ))))
+        );
+        assert_eq!(
+            image_name("image_name=example.org:8080"),
+            Err(Err::Error(ConfigError::Parse(Error::new(
+                ".org:8080",
+                ErrorKind::Tag
+            ))))
+        );
+        assert_eq!(
+            image_name("image_name=some-subsite.example.org:8080"),
+            Err(Err::Error(ConfigError::Parse(Error::new(
+                ".org:8080",
+                ErrorKind::Tag
+            ))))
+        );
+        assert_eq!(
+            image_name("image_name=example.123"),
+            Err(Err::Error(ConfigError::Parse(Error::new(
+                ".123",
+                ErrorKind::Tag
+            ))))
+        );
+        // underscores and dots are allowed; the extension starts at the last dot
+        assert_eq!(
+            image_name("image_name=signed_apertis.v2-rpi4.itb"),
+            Ok(("", ("signed_apertis.v2-rpi4", ".itb")))
+        );
+        assert_eq!(
+            image_name("image_name=foo.bar.itb"),
+            Ok(("", ("foo.bar", ".itb")))
+        );
+        assert_eq!(
+            image_name("image_name=foo.itb.bak"),
+            Ok((".bak", ("foo", ".itb")))
+        );
+        assert_eq!(
+            image_name("image_name=.itb"),
+            Err(Err::Error(ConfigError::Parse(Error::new(
+                ".itb",
+                ErrorKind::Tag
+            ))))
+        );
+    }
+
+    #[test]
+    fn test_image_name_with_ext() {
+        let exts = [".itb", ".fit", ".bin", ".uImage"];
+        assert_eq!(
+            image_name_with_ext("image_name=rpi4.fit", &exts),
+            Ok(("", ("rpi4", ".fit")))
+        );
+        assert_eq!(
+            image_name_with_ext("image_name=rpi4.uImage", &exts),
+            Ok(("", ("rpi4", ".uImage")))
+        );
+        // the longest matching extension wins
+        assert_eq!(
+            image_name_with_ext("image_name=rpi4.itblah", &[".itb", ".itblah"]),
+            Ok(("", ("rpi4", ".itblah")))
+        );
+        assert_eq!(
+            image_name_with_ext("image_name=rpi4.itb", &[".itb", ".itblah"]),
+            Ok(("", ("rpi4", ".itb")))
+        );
+        assert_eq!(
+            image_name_with_ext("image_name=rpi4.img", &exts),
+            Err(Err::Error(ConfigError::Parse(Error::new(
+                ".img",
+                ErrorKind::Tag
+            ))))
+        );
+    }
+
+    #[test]
+    fn test_image_path() {
+        assert_eq!(
+            image_path::<ConfigError>("image_name=/boot/signed-apertis-rpi4.itb"),
+            Ok(("", (Some("/boot/"), ("signed-apertis-rpi4", ".itb"))))
+        );
+        assert_eq!(
+            image_path::<ConfigError>("image_name=C:\\images\\rpi4.itb"),
+            Ok(("", (Some("C:\\images\\"), ("rpi4", ".itb"))))
+        );
+        assert_eq!(
+            image_path::<ConfigError>("image_name=\"/mnt/my images/rpi4.itb\""),
+            Ok(("", (Some("/mnt/my images/"), ("rpi4", ".itb"))))
+        );
+        // bare names are unchanged
+        assert_eq!(
+            image_path::<ConfigError>("image_name=signed-apertis-rpi4.itb"),
+            Ok(("", (None, ("signed-apertis-rpi4", ".itb"))))
+        );
+        assert_eq!(
+            image_path("image_name=/boot/.itb"),
+            Err(Err::Error(ConfigError::Parse(Error::new(
+                ".itb",
+                ErrorKind::Tag
+            ))))
+        );
+        // image_name_with_ext still takes bare names only
+        assert!(image_name("image_name=/boot/rpi4.itb").is_err());
+
+        let input = "[active]\nimage_name=/boot/rpi4.itb\nimage_version=ver_1\n\
+                     [passive]\nready_for_update_flag=true\nimage_name=/boot/rpi4-new.itb\n\
+                     image_version=ver_2\nupdate_status=testing\n";
+        let config = parse(input).unwrap();
+        assert_eq!(config.active().image_dir(), Some("/boot/"));
+        assert_eq!(config.active().image_name(), ("rpi4", ".itb"));
+        assert_eq!(config.passive().image_dir(), Some("/boot/"));
+        let staged = PassiveConfBuilder::new()
+            .ready_for_update(true)
+            .image_name(("rpi4-new", ".itb"))
+            .image_dir("/boot/")
+            .image_version(2)
+            .update_status(UpdateStatus::Testing)
+            .build();
+        assert_eq!(staged.as_ref(), Ok(config.passive()));
+        assert_eq!(parse(&format!("{}", config)), Ok(config.clone()));
+        #[cfg(feature = "alloc")]
+        assert_eq!(config.clone().into_owned().as_config(), config);
+    }
+
+    #[test]
+    fn test_image_name_escapes() {
+        assert_eq!(
+            image_path::<ConfigError>("image_name=\"a\\\"b.itb\"\n"),
+            Ok(("\n", (None, ("a\\\"b", ".itb"))))
+        );
+        // an escaped backslash is a separator, but an escaped quote is not
+        assert_eq!(
+            image_path::<ConfigError>("image_name=\"a\\\\b.itb\"\n"),
+            Ok(("\n", (Some("a\\\\"), ("b", ".itb"))))
+        );
+        assert_eq!(
+            image_name("image_name=\"a\\\\b.itb\"\n"),
+            Ok(("\n", ("a\\\\b", ".itb")))
+        );
+        // an escaped quote doesn't close the name
+        assert!(image_path::<ConfigError>("image_name=\"a\\\".itb\n").is_err());
+
+        let input = "[active]\nimage_name=\"my \\\"new\\\" image.itb\"\nimage_version=ver_1\n\
+                     [passive]\nready_for_update_flag=false\n";
+        let active = parse_active_only(input).unwrap();
+        assert_eq!(active.image_name(), ("my \\\"new\\\" image", ".itb"));
+        assert!(format!("{}", active).contains("image_name=\"my \\\"new\\\" image.itb\""));
+
+        #[cfg(feature = "alloc")]
+        {
+            assert_eq!(unescape("a\\\"b"), "a\"b");
+            assert_eq!(unescape("a\\\\b"), "a\\b");
+            assert_eq!(unescape("a\\b"), "a\\b");
+            assert!(matches!(unescape("signed-apertis-rpi4"), Cow::Borrowed(_)));
+        }
+    }
+
+    #[test]
+    fn test_image_name_strict() {
+        let exts = [".itb"];
+        assert_eq!(
+            image_name_strict("image_name=signed-apertis-rpi4.itb", &exts),
+            Ok(("", ("signed-apertis-rpi4", ".itb")))
+        );
+        for (input, name) in [
+            ("image_name=-foo.itb", "-foo"),
+            ("image_name=foo-.itb", "foo-"),
+            ("image_name=foo--bar.itb", "foo--bar"),
+        ] {
+            assert_eq!(
+                image_name_strict(input, &exts),
+                Err(Err::Error(ConfigError::Parse(Error::new(
+                    name,
+                    ErrorKind::Verify
+                ))))
+            );
+            // the lenient parser still accepts them
+            assert!(image_name_with_ext(input, &exts).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_image_name_quoted() {
+        assert_eq!(
+            image_name("image_name=\"my image.itb\" # spaces"),
+            Ok(("# spaces", ("my image", ".itb")))
+        );
+        assert_eq!(
+            image_name("image_name=\"v2 (final).build.itb\""),
+            Ok(("", ("v2 (final).build", ".itb")))
+        );
+        // unquoted names are unchanged
+        assert_eq!(
+            image_name("image_name=my image.itb"),
+            Err(Err::Error(ConfigError::Parse(Error::new(
+                " image.itb",
+                ErrorKind::Tag
+            ))))
+        );
+        // the extension must end the quoted name
+        assert_eq!(
+            image_name("image_name=\"my image.itb.bak\""),
+            Err(Err::Error(ConfigError::Parse(Error::new(
+                ".bak",
+                ErrorKind::Tag
+            ))))
+        );
+        assert_eq!(
+            image_name("image_name=\"my image.itb\n"),
+            Err(Err::Failure(ConfigError::Parse(Error::new(
+                "\"my image.itb\n",
+                ErrorKind::Char
+            ))))
+        );
+        assert!(parse(
+            "[active]\nimage_name=\"my image.itb\n[passive]\nready_for_update_flag=false\n"
+        )
+        .is_err());
+
+        let (_, (active, _)) = parse_config(
+            "[active]\nimage_name=\"my image.itb\"\nimage_version=ver_1\n[passive]\nready_for_update_flag=false\n",
+        )
+        .unwrap();
+        assert_eq!(active.image_name, ("my image", ".itb"));
+        #[cfg(feature = "alloc")]
+        assert_eq!(
+            active.to_string(),
+            "[active]\nimage_name=\"my image.itb\"\nimage_version=ver_1\n"
+        );
+    }
+
+    #[test]
+    fn test_display_label() {
+        let (_, label) = image_name("image_name=signed-apertis-rpi4.itb").unwrap();
+        assert_eq!(
+            format!("{}", DisplayLabel(label)),
+            "signed-apertis-rpi4.itb"
+        );
+        let (_, label) = image_name("image_name=foo.bar.itb").unwrap();
+        assert_eq!(format!("{}", DisplayLabel(label)), "foo.bar.itb");
+    }
+
+    #[test]
+    fn test_image_format() {
+        assert_eq!(ImageFormat::from(".itb"), ImageFormat::Itb);
+        assert_eq!(ImageFormat::from(".fit"), ImageFormat::Fit);
+        assert_eq!(ImageFormat::from(".bin"), ImageFormat::Bin);
+        assert_eq!(ImageFormat::from(".uImage"), ImageFormat::UImage);
+        assert_eq!(ImageFormat::from(".img"), ImageFormat::Other(".img"));
+        assert_eq!(
+            ActiveConf::new(("rpi4", ".fit"), 1).image_format(),
+            ImageFormat::Fit
+        );
+        let passive = PassiveConfBuilder::new().ready_for_update(false);
+        assert_eq!(passive.build().unwrap().image_format(), None);
+    }
+
+    #[test]
+    fn test_image_version() {
+        // libc_println!(
+        //     "image_version: {:?}",
+        //     image_version("image_version=ver_612634867 ")
+        // );
+        assert_eq!(
+            image_version::<ConfigError>(
+                "image_version=ver_612634867
+            "
+            ),
+            Ok(("\n            ", (612634867)))
+        );
+        assert_eq!(
+            image_version("image_version=ver_111.222.345"),
+            Err(Err::Error(ConfigError::Parse(Error::new(
+                ".222.345",
+                ErrorKind::MultiSpace
+            ))))
+        );
+        // a version that overflows u32 is an error, not a panic
+        assert_eq!(
+            image_version("image_version=ver_99999999999999999999\n"),
+            Err(Err::Failure(ConfigError::InvalidVersion(
+                "99999999999999999999"
+            )))
+        );
+        assert_eq!(
+            image_version::<ConfigError>("image_version=ver_4294967295\n"),
+            Ok(("\n", u32::MAX))
+        );
+        // a missing version number is an error, not a panic
+        assert_eq!(
+            image_version("image_version=ver_\n"),
+            Err(Err::Failure(ConfigError::InvalidVersion("")))
+        );
+        assert_eq!(
+            image_version("image_version=ver_abc\n"),
+            Err(Err::Failure(ConfigError::InvalidVersion("abc")))
+        );
+        // hex build IDs
+        assert_eq!(
+            image_version::<ConfigError>("image_version=ver_0xFF\n"),
+            image_version("image_version=ver_255\n")
+        );
+        assert_eq!(
+            image_version::<ConfigError>("image_version=ver_0x1a2b3c\n"),
+            Ok(("\n", 0x1a2b3c))
+        );
+        assert_eq!(
+            image_version("image_version=ver_0x100000000\n"),
+            Err(Err::Failure(ConfigError::InvalidVersion("0x100000000")))
+        );
+        assert_eq!(
+            image_version("image_version=ver_0x\n"),
+            Err(Err::Failure(ConfigError::InvalidVersion("0x")))
+        );
+    }
+
+    #[test]
+    fn test_image_version_saturating() {
+        let oversized = "image_version=ver_99999999999999999999\n";
+        assert_eq!(image_version_saturating(oversized), Ok(("\n", MAX_VERSION)));
+        assert_eq!(
+            image_version(oversized),
+            Err(Err::Failure(ConfigError::InvalidVersion(
+                "99999999999999999999"
+            )))
+        );
+        assert_eq!(
+            image_version_saturating("image_version=ver_0x1ffffffff"),
+            Ok(("", MAX_VERSION))
+        );
+        assert_eq!(
+            image_version_saturating("image_version = ver_123 "),
+            Ok(("", 123))
+        );
+        assert_eq!(
+            image_version_saturating("image_version=ver_x"),
+            Err(Err::Failure(ConfigError::InvalidVersion("x")))
+        );
+    }
+
+    #[test]
+    fn test_semver_version() {
+        assert_eq!(
+            semver_version::<ConfigError>("image_version=ver_1.2.3 "),
+            Ok((
+                "",
+                ImageVersion {
+                    major: 1,
+                    minor: 2,
+                    patch: 3
+                }
+            ))
+        );
+        assert_eq!(
+            semver_version("image_version=ver_1.2 "),
+            Err(Err::Error(ConfigError::Parse(Error::new(
+                " ",
+                ErrorKind::Tag
+            ))))
+        );
+        assert!(
+            ImageVersion::from(2)
+                > ImageVersion {
+                    major: 1,
+                    minor: 9,
+                    patch: 9
+                }
+        );
+        assert!(
+            ImageVersion {
+                major: 1,
+                minor: 2,
+                patch: 3
+            } < ImageVersion {
+                major: 1,
+                minor: 10,
+                patch: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_image_hash() {
+        // the SHA-256 of an empty file
+        let hex = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let hash = [
+            0xe3, 0xb0, 0xc4, 0x42, 0x98, 0xfc, 0x1c, 0x14, 0x9a, 0xfb, 0xf4, 0xc8, 0x99, 0x6f,
+            0xb9, 0x24, 0x27, 0xae, 0x41, 0xe4, 0x64, 0x9b, 0x93, 0x4c, 0xa4, 0x95, 0x99, 0x1b,
+            0x78, 0x52, 0xb8, 0x55,
+        ];
+        assert_eq!(
+            image_hash::<ConfigError>(&format!("image_hash={}\n", hex)),
+            Ok(("\n", hash))
+        );
+        assert_eq!(
+            image_hash::<ConfigError>(&format!("image_hash={}\n", hex.to_uppercase())),
+            Ok(("\n", hash))
+        );
+        assert_eq!(
+            image_hash("image_hash=e3b0c442\n"),
+            Err(Err::Failure(ConfigError::InvalidHash("e3b0c442")))
+        );
+        let not_hex = format!("image_hash={}", hex.replace('e', "g"));
+        assert_eq!(
+            image_hash(&not_hex),
+            Err(Err::Failure(ConfigError::InvalidHash(&not_hex[11..])))
+        );
+
+        let input = format!(
+            "[active]
+            image_name=xx.itb
+            image_version=ver_1
+            image_hash={hex}
+
+            [passive]
+            ready_for_update_flag=true
+            image_name=yy.itb
+            image_version=ver_2
+            image_hash={hex}
+            update_status=testing"
+        );
+        let config = parse(&input).unwrap();
+        assert_eq!(config.active().image_hash(), Some(hash));
+        assert_eq!(config.passive().image_hash(), Some(hash));
+        assert_eq!(
+            format!("{}", config.active()),
+            format!("[active]\nimage_name=xx.itb\nimage_version=ver_1\nimage_hash={hex}\n")
+        );
+    }
+
+    #[test]
+    fn test_image_hash_case() {
+        let lower = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let upper = lower.to_uppercase();
+        let mixed = "E3b0C44298FC1c149afbf4c8996FB92427ae41e4649b934ca495991b7852B855";
+        let config = |hex: &str| {
+            format!(
+                "[active]\nimage_name=xx.itb\nimage_version=ver_1\nimage_hash={hex}\n\
+                 [passive]\nready_for_update_flag=false\n"
+            )
+        };
+        let (lower_input, upper_input, mixed_input) =
+            (config(lower), config(&upper), config(mixed));
+        let from_lower = parse(&lower_input).unwrap();
+        let from_upper = parse(&upper_input).unwrap();
+        assert_eq!(from_upper, from_lower);
+        assert_eq!(parse(&mixed_input), Ok(from_lower.clone()));
+        assert!(diff(&from_lower, &from_upper).is_empty());
+        // always re-emitted in lowercase
+        assert_eq!(format!("{}", from_upper), format!("{}", from_lower));
+        assert!(format!("{}", from_upper).contains(lower));
+    }
+
+    #[test]
+    fn test_boot_attempts() {
+        assert_eq!(
+            boot_attempts::<ConfigError>("boot_attempts=3\n"),
+            Ok(("\n", 3))
+        );
+        assert_eq!(
+            boot_attempts("boot_attempts=three"),
+            Err(Err::Error(ConfigError::Parse(Error::new(
+                "three",
+                ErrorKind::Digit
+            ))))
+        );
+    }
+
+    #[test]
+    fn test_passive_is_empty() {
+        let (_, wiped) = passive_config("[passive]\nready_for_update_flag=false\n").unwrap();
+        assert!(wiped.is_empty());
+        let (_, wiped) = passive_config(
+            "[passive]\nready_for_update_flag=false\nimage_name=\nimage_version=none\n",
+        )
+        .unwrap();
+        assert!(wiped.is_empty());
+        let (_, staged) = passive_config(
+            "[passive]\nready_for_update_flag=true\nimage_name=xx.itb\n\
+             image_version=ver_2\nupdate_status=updating\n",
+        )
+        .unwrap();
+        assert!(!staged.is_empty());
+        let ready = PassiveConfBuilder::new().ready_for_update(true);
+        assert!(!ready.build().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_passive_clear() {
+        let input = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n\
+                     [passive]\nready_for_update_flag=true\nimage_name=/boot/yy.itb\n\
+                     image_version=ver_2.0.1\nupdate_status=failed\nboot_attempts=3\n\
+                     test_window_secs=60\ndry_run=true\nsignature=abcd\n";
+        let mut config = parse(input).unwrap();
+        config.passive.clear();
+        assert!(config.passive().is_empty());
+        let (_, empty) = passive_config("[passive]\nready_for_update_flag=false\n").unwrap();
+        assert_eq!(config.passive, empty);
+
+        let written = format!("{}", config);
+        assert!(written.ends_with("[passive]\nready_for_update_flag=false\n"));
+        assert_eq!(parse(&written), Ok(config));
+    }
+
+    #[test]
+    fn test_staged_image() {
+        let passive = |text: &'static str| passive_config(text).unwrap().1;
+        let staged = passive(
+            "[passive]\nready_for_update_flag=true\nimage_name=yy.itb\n\
+             image_version=ver_2\nupdate_status=testing\n",
+        );
+        assert_eq!(staged.staged_image(), Ok(Some((("yy", ".itb"), 2))));
+
+        // ready, but with nothing (or only half of it) staged
+        let ready = passive("[passive]\nready_for_update_flag=true\n");
+        assert_eq!(
+            ready.staged_image(),
+            Err(ConfigError::MissingField(IMAGE_NAME_KEY))
+        );
+        let half = PassiveConfBuilder::new()
+            .ready_for_update(true)
+            .image_name(("yy", ".itb"))
+            .build()
+            .unwrap();
+        assert_eq!(
+            half.staged_image(),
+            Err(ConfigError::MissingField(IMAGE_VERSION_KEY))
+        );
+
+        // not ready isn't an error, even with an image named
+        let idle = passive("[passive]\nready_for_update_flag=false\n");
+        assert_eq!(idle.staged_image(), Ok(None));
+        let named = passive("[passive]\nready_for_update_flag=false\nimage_name=yy.itb\n");
+        assert_eq!(named.staged_image(), Ok(None));
+    }
+
+    #[test]
+    fn test_dry_run() {
+        assert_eq!(dry_run::<ConfigError>("dry_run=yes"), Ok(("", true)));
+        let (_, passive) = passive_config(
+            "[passive]
+            ready_for_update_flag=true
+            image_name=xx.itb
+            image_version=ver_2
+            dry_run = TRUE
+            update_status=updating
+            ",
+        )
+        .unwrap();
+        assert_eq!(passive.dry_run(), Some(true));
+        assert!(passive.is_dry_run());
+        assert_eq!(passive.unknown_keys(), 0);
+        let (_, passive) = passive_config("[passive]\nready_for_update_flag=true\n").unwrap();
+        assert_eq!(passive.dry_run(), None);
+        assert!(!passive.is_dry_run());
+        let passive = PassiveConfBuilder::new()
+            .ready_for_update(true)
+            .dry_run(false);
+        assert!(!passive.build().unwrap().is_dry_run());
+    }
+
+    #[test]
+    fn test_staged_at() {
+        assert_eq!(
+            staged_at::<ConfigError>("staged_at=1700000000"),
+            Ok(("", 1_700_000_000))
+        );
+        // past what a u32 can hold
+        assert_eq!(
+            staged_at::<ConfigError>("staged_at=4294967296"),
+            Ok(("", 1 << 32))
+        );
+        assert!(staged_at::<ConfigError>("staged_at=18446744073709551616").is_err());
+        let (_, passive) = passive_config(
+            "[passive]
+            ready_for_update_flag=true
+            staged_at=1700000000
+            image_name=xx.itb
+            image_version=ver_2
+            update_status=updating
+            ",
+        )
+        .unwrap();
+        assert_eq!(passive.staged_at(), Some(1_700_000_000));
+        assert_eq!(passive.unknown_keys(), 0);
+        assert!(format!("{}", passive).ends_with("staged_at=1700000000\n"));
+        let (_, passive) = passive_config("[passive]\nready_for_update_flag=true\n").unwrap();
+        assert_eq!(passive.staged_at(), None);
+    }
+
+    #[test]
+    fn test_number_overflow() {
+        let active = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n";
+        let passive = "[passive]\nready_for_update_flag=true\n";
+        let too_large = "99999999999999999999999";
+        for (section, key) in [
+            (active, MIN_HWREV_KEY),
+            (passive, MIN_HWREV_KEY),
+            (passive, BOOT_ATTEMPTS_KEY),
+            (passive, TEST_WINDOW_SECS_KEY),
+            (passive, STAGED_AT_KEY),
+        ] {
+            let field = format!("{}={}\n", key, too_large);
+            let input = if section == active {
+                format!("{}{}{}", active, field, passive)
+            } else {
+                format!("{}{}{}", active, passive, field)
+            };
+            assert_eq!(
+                parse(&input),
+                Err(ConfigError::NumberTooLarge(key, too_large)),
+                "{}",
+                key
+            );
+        }
+        // just past each type's range
+        assert_eq!(
+            boot_attempts("boot_attempts=4294967296"),
+            Err(Err::Failure(ConfigError::NumberTooLarge(
+                BOOT_ATTEMPTS_KEY,
+                "4294967296"
+            )))
+        );
+        assert_eq!(
+            staged_at("staged_at=18446744073709551616"),
+            Err(Err::Failure(ConfigError::NumberTooLarge(
+                STAGED_AT_KEY,
+                "18446744073709551616"
+            )))
+        );
+        assert_eq!(
+            staged_at::<ConfigError>("staged_at=18446744073709551615"),
+            Ok(("", u64::MAX))
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                ConfigError::NumberTooLarge(MIN_HWREV_KEY, "4294967296")
+            ),
+            "min_hwrev out of range: 4294967296"
+        );
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let (rest, backoff) =
+            retry_backoff::<ConfigError>("retry_backoff_secs=1,2,4,8 # doubling").unwrap();
+        assert_eq!(rest, "# doubling");
+        assert_eq!(backoff.as_slice(), &[1, 2, 4, 8]);
+        assert_eq!(RetryBackoff::new(&[1, 2, 4, 8]), Some(backoff));
+        assert_eq!(RetryBackoff::new(&[0; MAX_BACKOFF_STEPS + 1]), None);
+        let (_, passive) = passive_config(
+            "[passive]
+            ready_for_update_flag=true
+            retry_backoff_secs=30
+            ",
+        )
+        .unwrap();
+        assert_eq!(passive.retry_backoff(), Some(&[30][..]));
+        let passive = PassiveConfBuilder::new()
+            .ready_for_update(true)
+            .retry_backoff(backoff)
+            .build()
+            .unwrap();
+        let text = format!("{}", passive);
+        assert!(text.ends_with("retry_backoff_secs=1,2,4,8\n"));
+        assert_eq!(passive_config(&text), Ok(("", passive)));
+    }
+
+    #[test]
+    fn test_retry_backoff_malformed() {
+        for value in [
+            "1,,4",
+            "1,2,",
+            "",
+            "1;2",
+            "+1",
+            "4294967296",
+            "1,2,3,4,5,6,7,8,9",
+        ] {
+            let input = format!("retry_backoff_secs={}", value);
+            assert_eq!(
+                retry_backoff(&input),
+                Err(Err::Failure(ConfigError::InvalidBackoff(value)))
+            );
+        }
+        let input = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n\
+                     [passive]\nready_for_update_flag=true\nretry_backoff_secs=1,x\n";
+        assert_eq!(parse(input), Err(ConfigError::InvalidBackoff("1,x")));
+    }
+
+    #[test]
+    fn test_is_update_in_progress() {
+        let passive = |status: Option<UpdateStatus<'static>>| {
+            let builder = PassiveConfBuilder::new().ready_for_update(true);
+            match status {
+                Some(status) => builder.update_status(status),
+                None => builder,
+            }
+            .build()
+            .unwrap()
+        };
+        assert!(passive(Some(UpdateStatus::Updating)).is_update_in_progress());
+        assert!(passive(Some(UpdateStatus::Testing)).is_update_in_progress());
+        assert!(!passive(Some(UpdateStatus::Success)).is_update_in_progress());
+        assert!(!passive(Some(UpdateStatus::Failed)).is_update_in_progress());
+        assert!(!passive(None).is_update_in_progress());
+    }
+
+    #[test]
+    fn test_test_window_secs() {
+        assert_eq!(
+            test_window_secs::<ConfigError>("test_window_secs = 600\n"),
+            Ok(("\n", 600))
+        );
+        let (_, passive) = passive_config(
+            "[passive]
+            ready_for_update_flag=true
+            image_name=xx.itb
+            test_window_secs=600
+            image_version=ver_2
+            update_status=testing
+            boot_attempts=1
+            ",
+        )
+        .unwrap();
+        assert_eq!(passive.test_window_secs(), Some(600));
+        assert_eq!(passive.boot_attempts(), Some(1));
+        assert_eq!(passive.unknown_keys(), 0);
+        let passive = PassiveConfBuilder::new().ready_for_update(false);
+        assert_eq!(passive.build().unwrap().test_window_secs(), None);
+    }
+
+    #[test]
+    fn test_passive_name() {
+        assert_eq!(
+            passive_name::<ConfigError>("image_name=xx.itb\n"),
+            Ok(("\n", Some((None, ("xx", ".itb")))))
+        );
+        assert_eq!(
+            passive_name::<ConfigError>("image_name=\n"),
+            Ok(("\n", None))
+        );
+        assert_eq!(passive_name::<ConfigError>("image_name="), Ok(("", None)));
+        assert_eq!(
+            passive_name("image_name=xx.img\n"),
+            Err(Err::Error(ConfigError::Parse(Error::new(
+                "xx.img\n",
+                ErrorKind::Tag
+            ))))
+        );
+    }
+
+    #[test]
+    fn test_passive_version() {
+        assert_eq!(
+            passive_version::<ConfigError>("image_version=none\n"),
+            Ok(("\n", None))
+        );
+        assert_eq!(
+            passive_version::<ConfigError>("image_version=ver_7\n"),
+            Ok(("\n", Some((7, None))))
+        );
+        // `none` is only meaningful for the optional passive version
+        assert!(active_config("[active]\nimage_name=xx.itb\nimage_version=none\n").is_err());
+    }
+
+    #[test]
+    fn test_ready_for_update() {
+        assert_eq!(
+            ready_for_update::<ConfigError>("ready_for_update_flag=true"),
+            Ok(("", true))
+        );
+        assert_eq!(
+            ready_for_update::<ConfigError>("ready_for_update_flag=TRUE"),
+            Ok(("", true))
+        );
+        assert_eq!(
+            ready_for_update::<ConfigError>("ready_for_update_flag=False"),
+            Ok(("", false))
+        );
+        for (spelling, flag) in [
+            ("1", true),
+            ("yes", true),
+            ("On", true),
+            ("0", false),
+            ("NO", false),
+            ("off", false),
+        ] {
+            assert_eq!(
+                ready_for_update::<ConfigError>(&format!("ready_for_update_flag={}\n", spelling)),
+                Ok(("\n", flag))
+            );
+        }
+        assert_eq!(
+            ready_for_update("ready_for_update_flag=maybe"),
+            Err(Err::Error(ConfigError::Parse(Error::new(
+                "maybe",
+                ErrorKind::Tag
+            ))))
+        );
+        assert_eq!(
+            ready_for_update("ready_for_update_flag=yesterday"),
+            Err(Err::Error(ConfigError::Parse(Error::new(
+                "terday",
+                ErrorKind::MultiSpace
+            ))))
+        );
+    }
+
+    #[test]
+    fn test_tag_no_case_ascii() {
+        assert_eq!(
+            tag_no_case_ascii::<ConfigError>("true")("TrUe rest"),
+            Ok((" rest", "TrUe"))
+        );
+        assert_eq!(
+            tag_no_case_ascii("true")("tru"),
+            Err(Err::Error(ConfigError::Parse(Error::new(
+                "tru",
+                ErrorKind::Tag
+            ))))
+        );
+    }
+
+    #[test]
+    fn test_update_status() {
+        // libc_println!(
+        //     "update_status: {:?}",
+        //     update_status("update_status=updating")
+        // );
+        assert_eq!(
+            update_status("update_status=updating"),
+            Ok(("", Some(UpdateStatus::Updating)))
+        );
+        assert_eq!(
+            update_status("update_status=SUCCESS"),
+            Ok(("", Some(UpdateStatus::Success)))
+        );
+        assert_eq!(
+            update_status("update_status=Updating"),
+            Ok(("", Some(UpdateStatus::Updating)))
+        );
+        assert_eq!(
+            update_status("update_status=failed"),
+            Ok(("", Some(UpdateStatus::Failed)))
+        );
+        assert_eq!(
+            update_status("update_status=committed"),
+            Ok(("", Some(UpdateStatus::Committed)))
+        );
+        assert_eq!(update_status("update_status=none "), Ok(("", None)));
+        assert_eq!(update_status("update_status=NONE"), Ok(("", None)));
+        // a freshly wiped slot parses
+        let wiped = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n\
+                     [passive]\nready_for_update_flag=false\nimage_name=\n\
+                     image_version=none\nupdate_status=none\n";
+        assert!(parse(wiped).unwrap().passive().is_empty());
+    }
+
+    #[test]
+    fn test_update_status_try_from() {
+        assert_eq!(UpdateStatus::try_from("success"), Ok(UpdateStatus::Success));
+        assert_eq!(UpdateStatus::try_from("FAILED"), Ok(UpdateStatus::Failed));
+        for (keyword, status) in STATUS_TABLE {
+            assert_eq!(UpdateStatus::try_from(*keyword), Ok(*status));
+            assert_eq!(status.as_str(), *keyword);
+            assert_eq!(UpdateStatus::try_from(status.as_str()), Ok(*status));
+            assert_eq!(format!("{}", status), *keyword);
+        }
+        const TESTING: &str = UpdateStatus::Testing.as_str();
+        assert_eq!(TESTING, "testing");
+        assert_eq!(
+            UpdateStatus::try_from("rebooting"),
+            Err(ConfigError::InvalidStatus("rebooting"))
+        );
+    }
+
+    #[test]
+    fn test_channel() {
+        for (input, expected) in [
+            ("channel=stable", Channel::Stable),
+            ("channel=beta", Channel::Beta),
+            ("channel=Dev", Channel::Dev),
+        ] {
+            assert_eq!(channel::<ConfigError>(input), Ok(("", expected)));
+        }
+        assert_eq!(
+            channel("channel=nightly # typo"),
+            Err(Err::Failure(ConfigError::UnknownChannel("nightly")))
+        );
+        for (keyword, channel) in CHANNEL_TABLE {
+            assert_eq!(Channel::try_from(*keyword), Ok(*channel));
+            assert_eq!(format!("{}", channel), *keyword);
+        }
+
+        let input = "[active]\nimage_name=xx.itb\nimage_version=ver_1\nchannel=stable\n\
+                     [passive]\nready_for_update_flag=true\nchannel=beta\n";
+        let config = parse(input).unwrap();
+        assert_eq!(config.active().channel(), Some(Channel::Stable));
+        assert_eq!(config.passive().channel(), Some(Channel::Beta));
+        assert_eq!(parse(&format!("{}", config)), Ok(config));
+        // like a malformed `update_status`, the bad keyword is reported rather than skipped
+        assert_eq!(
+            parse(&input.replace("beta", "nightly")),
+            Err(ConfigError::UnknownChannel("nightly"))
+        );
+        assert_eq!(
+            parse("[active]\nimage_name=xx.itb\nimage_version=ver_1\n[passive]\nready_for_update_flag=true\n")
+                .unwrap()
+                .active()
+                .channel(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_min_hwrev() {
+        assert_eq!(min_hwrev::<ConfigError>("min_hwrev=3"), Ok(("", 3)));
+        assert!(min_hwrev::<ConfigError>("min_hwrev=rev3").is_err());
+
+        let input = "[board]\nmodel=rpi4\nhwrev=2\n\
+                     [active]\nimage_name=xx.itb\nimage_version=ver_1\nmin_hwrev=1\n\
+                     [passive]\nready_for_update_flag=true\nimage_name=yy.itb\n\
+                     image_version=ver_2\nmin_hwrev=3\nupdate_status=testing\n";
+        let config = parse(input).unwrap();
+        assert_eq!(config.active().min_hwrev(), Some(1));
+        assert_eq!(config.passive().min_hwrev(), Some(3));
+        assert_eq!(parse(&format!("{}", config)), Ok(config.clone()));
+
+        // the passive image is picked, but needs a newer board than this one
+        let hwrev = config.board.as_ref().unwrap().hwrev();
+        assert_eq!(config.boot_target(), BootTarget::Passive);
+        assert!(!config.image_allowed(hwrev));
+        assert!(config.image_allowed(3));
+        assert!(config.active().allows_hwrev(hwrev));
+        // without a `min_hwrev`, any board will do
+        let input = input.replace("min_hwrev=3\n", "");
+        let config = parse(&input).unwrap();
+        assert_eq!(config.passive().min_hwrev(), None);
+        assert!(config.image_allowed(0));
+    }
+
+    #[test]
+    fn test_update_status_transitions() {
+        use UpdateStatus::*;
+        assert!(Updating.can_transition_to(Testing));
+        assert!(Testing.can_transition_to(Success));
+        assert!(Testing.can_transition_to(Failed));
+        assert!(Success.can_transition_to(Committed));
+
+        assert!(!Updating.can_transition_to(Updating));
+        assert!(!Updating.can_transition_to(Success));
+        assert!(!Testing.can_transition_to(Updating));
+        assert!(!Testing.can_transition_to(Testing));
+        assert!(!Success.can_transition_to(Updating));
+        assert!(!Success.can_transition_to(Testing));
+        assert!(!Success.can_transition_to(Success));
+        assert!(!Updating.can_transition_to(Failed));
+        assert!(!Success.can_transition_to(Failed));
+        assert!(!Failed.can_transition_to(Updating));
+        assert!(!Failed.can_transition_to(Testing));
+        assert!(!Failed.can_transition_to(Success));
+        assert!(!Failed.can_transition_to(Failed));
+        assert!(!Testing.can_transition_to(Committed));
+        assert!(!Failed.can_transition_to(Committed));
+        for next in [Updating, Testing, Success, Committed, Failed] {
+            assert!(!Committed.can_transition_to(next));
+        }
+    }
+
+    #[test]
+    fn test_update_status_ord() {
+        use UpdateStatus::*;
+        assert!(Updating < Testing);
+        assert!(Testing < Success);
+        assert!(Updating < Success);
+        assert!(Failed < Updating);
+        assert!(Success < Committed);
+        let mut slots = [Success, Committed, Failed, Testing, Updating];
+        slots.sort();
+        assert_eq!(slots, [Failed, Updating, Testing, Success, Committed]);
+    }
+
+    #[test]
+    fn test_value_trimming() {
+        assert_eq!(
+            image_version::<ConfigError>("image_version=ver_123   "),
+            Ok(("", 123))
+        );
+        assert_eq!(
+            image_version::<ConfigError>("image_version = ver_123 \t\n"),
+            Ok(("\n", 123))
+        );
+        assert_eq!(
+            update_status("update_status=testing  \t# booted once"),
+            Ok(("# booted once", Some(UpdateStatus::Testing)))
+        );
+        assert_eq!(
+            boot_attempts::<ConfigError>("boot_attempts=\t3  "),
+            Ok(("", 3))
+        );
+        assert_eq!(
+            image_name("image_name=  xx.itb  \n"),
+            Ok(("\n", ("xx", ".itb")))
+        );
+        // spaces inside quotes belong to the name
+        assert_eq!(
+            image_name("image_name= \" xx.itb\"  \n"),
+            Ok(("\n", (" xx", ".itb")))
+        );
+        let (_, passive) = passive_config(
+            "[passive]\nready_for_update_flag=true   \nimage_name=xx.itb\n\
+             image_version=ver_123   \nupdate_status=success\t\n",
+        )
+        .unwrap();
+        assert_eq!(passive.image_version(), Some(123));
+        assert_eq!(passive.update_status(), Some(UpdateStatus::Success));
+    }
+
+    #[test]
+    fn test_key_value() {
+        assert_eq!(image_name("image_name = xx.itb"), Ok(("", ("xx", ".itb"))));
+        assert_eq!(
+            image_version::<ConfigError>("image_version\t=  ver_123\n"),
+            Ok(("\n", 123))
+        );
+        assert_eq!(
+            ready_for_update::<ConfigError>("ready_for_update_flag= true"),
+            Ok(("", true))
+        );
+        assert_eq!(
+            update_status("update_status =testing"),
+            Ok(("", Some(UpdateStatus::Testing)))
+        );
+        assert_eq!(
+            key_value("image_name", name_chars(&['-']))("image_name xx"),
+            Err(Err::Error(ConfigError::Parse(Error::new(
+                "xx",
+                ErrorKind::Char
+            ))))
+        );
+        let spaced = passive_config(
+            "[passive]
+            ready_for_update_flag = true
+            image_name = xx.itb
+            image_version = ver_1.2.3
+            update_status = success
+            boot_attempts = 1
+            ",
+        );
+        assert_eq!(
+            spaced.as_ref().map(|(_, conf)| conf.update_status),
+            Ok(Some(UpdateStatus::Success))
+        );
+        assert_eq!(
+            spaced,
+            passive_config(
+                "[passive]
+                ready_for_update_flag=true
+                image_name=xx.itb
+                image_version=ver_1.2.3
+                update_status=success
+                boot_attempts=1
+                "
+            )
+        );
+    }
+
+    #[test]
+    fn test_section_pairs() {
+        let input = "[passive]
+            ready_for_update_flag=true
+            # staged by the update daemon
+            image_name = xx.itb   # signed
+            not a pair
+            unknown_key=whatever
+            update_status=
+
+            [active]
+            image_name=yy.itb";
+        let mut pairs = section_pairs(input);
+        assert_eq!(pairs.next(), Some(("ready_for_update_flag", "true")));
+        assert_eq!(pairs.next(), Some(("image_name", "xx.itb")));
+        assert_eq!(pairs.next(), Some(("unknown_key", "whatever")));
+        assert_eq!(pairs.next(), Some(("update_status", "")));
+        assert_eq!(pairs.next(), None);
+
+        // the header is optional, and the last line needn't end with a newline
+        let mut pairs = section_pairs("watchdog_timeout=30\r\nauto_rollback=true");
+        assert_eq!(pairs.next(), Some(("watchdog_timeout", "30")));
+        assert_eq!(pairs.next(), Some(("auto_rollback", "true")));
+        assert_eq!(pairs.next(), None);
+
+        // a `#` only starts a comment outside quotes
+        let mut pairs =
+            section_pairs("image_name=\"foo#bar.itb\" # signed\nimage_version=ver_5 # bump");
+        assert_eq!(pairs.next(), Some(("image_name", "\"foo#bar.itb\"")));
+        assert_eq!(pairs.next(), Some(("image_version", "ver_5")));
+        assert_eq!(pairs.next(), None);
+    }
+
+    #[test]
+    fn test_comment_after_value() {
+        let input = "[active]\nimage_name=\"foo#bar.itb\"\nimage_version=ver_5 # bump\n\
+                     [passive]\nready_for_update_flag=false # nothing staged\n";
+        let config = parse(input).unwrap();
+        assert_eq!(config.active.image_name(), ("foo#bar", ".itb"));
+        assert_eq!(config.active.image_version(), 5);
+        assert!(config.passive.is_empty());
+        assert_eq!(
+            image_path::<ConfigError>("image_name=\"foo#bar.itb\"   # signed\n"),
+            Ok(("# signed\n", (None, ("foo#bar", ".itb"))))
+        );
+        // unquoted, the `#` starts a comment and cuts the name short
+        assert!(parse(&input.replace("\"foo#bar.itb\"", "foo#bar.itb")).is_err());
+    }
+
+    #[test]
+    fn test_comment() {
+        assert_eq!(
+            comment::<ConfigError>("# a comment\nrest"),
+            Ok(("\nrest", "# a comment"))
+        );
+        assert_eq!(
+            multispace_or_comment0::<ConfigError>("  # one\n\n# two\n  image_name="),
+            Ok(("image_name=", "  # one\n\n# two\n  "))
+        );
+        assert_eq!(
+            multispace_or_comment0::<ConfigError>("image_name="),
+            Ok(("image_name=", ""))
+        );
+    }
+
+    #[test]
+    fn test_line_ending_or_eof() {
+        assert_eq!(
+            line_ending_or_eof::<ConfigError>("\nrest"),
+            Ok(("rest", "\n"))
+        );
+        assert_eq!(
+            line_ending_or_eof::<ConfigError>("\r\nrest"),
+            Ok(("rest", "\r\n"))
+        );
+        assert_eq!(line_ending_or_eof::<ConfigError>(""), Ok(("", "")));
+        assert_eq!(
+            end_of_line::<ConfigError>("  # signed\r\nrest"),
+            Ok(("rest", "  # signed\r\n"))
+        );
+        assert_eq!(
+            end_of_line("x\n"),
+            Err(Err::Error(ConfigError::Parse(Error::new(
+                "x\n",
+                ErrorKind::Eof
+            ))))
+        );
+    }
+
+    #[test]
+    fn test_parse_at_eof() {
+        // no trailing newline or whitespace after the last value
+        assert_eq!(
+            passive_config("[passive]\nready_for_update_flag=true\nimage_name=xx.itb\nimage_version=ver_2\nupdate_status=success"),
+            Ok((
+                "",
+                PassiveConf {
+                    passive_config: ConfigKeys::Passive,
+                    ready_for_update_flag: true,
+                    image_name: Some(("xx", ".itb")),
+                    image_dir: None,
+                    image_version: Some(2),
+                    image_semver: None,
+                    image_hash: None,
+                    channel: None,
+                    min_hwrev: None,
+                    update_status: Some(UpdateStatus::Success),
+                    boot_attempts: None,
+                    test_window_secs: None,
+                    dry_run: None,
+                    staged_at: None,
+                    retry_backoff: None,
+                    unknown_fields: UnknownFields::EMPTY,
+                }
+            ))
+        );
+        assert_eq!(
+            passive_config("[passive]\nready_for_update_flag=false")
+                .map(|(next_input, conf)| (next_input, conf.ready_for_update_flag)),
+            Ok(("", false))
+        );
+        for input in [
+            "[active]\nimage_name=xx.itb\nimage_version=ver_1\n[passive]\nready_for_update_flag=true\nimage_name=yy.itb\nimage_version=ver_2\nupdate_status=success",
+            "[passive]\nready_for_update_flag=true\nimage_name=yy.itb\nimage_version=ver_2\nupdate_status=success\n[active]\nimage_name=xx.itb\nimage_version=ver_1",
+            "[active]\nimage_name=xx.itb\nimage_version=ver_1\n[passive]\nready_for_update_flag=true\nimage_name=yy.itb\nimage_version=ver_2\nupdate_status=success # done",
+        ] {
+            let (next_input, (_, passive)) = parse_config(input).unwrap();
+            assert_eq!(next_input, "");
+            assert_eq!(passive.update_status, Some(UpdateStatus::Success));
+            assert!(parse(input).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_active_conf() {
+        libc_println!(
+            "active_config: {:?}",
+            active_config(
+                "[active]
+            image_name=xx.itb
+            image_version=ver_123 "
+            )
+        );
+        assert_eq!(
+            active_config(
+                "[active]
+            image_name=xx.itb
+            image_version=ver_123 "
+            ),
+            Ok((
+                "",
+                ActiveConf {
+                    active_config: ConfigKeys::Active,
+                    image_name: ("xx", ".itb"),
+                    image_dir: None,
+                    image_version: 123,
+                    image_semver: None,
+                    image_hash: None,
+                    channel: None,
+                    min_hwrev: None,
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_active_conf_with_comments() {
+        assert_eq!(
+            active_config(
+                "[active]
+            # the image that booted last
+            image_name=xx.itb # signed
+            image_version=ver_123 "
+            ),
+            Ok((
+                "",
+                ActiveConf {
+                    active_config: ConfigKeys::Active,
+                    image_name: ("xx", ".itb"),
+                    image_dir: None,
+                    image_version: 123,
+                    image_semver: None,
+                    image_hash: None,
+                    channel: None,
+                    min_hwrev: None,
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_passive_conf() {
+        assert_eq!(
+            passive_config(
+                "
+                [passive]
+                ready_for_update_flag=true
+                image_name=xx.itb
+                image_version=ver_123
+                update_status=updating "
+            ),
+            Ok((
+                "",
+                PassiveConf {
+                    passive_config: ConfigKeys::Passive,
+                    ready_for_update_flag: true,
+                    image_name: Some(("xx", ".itb")),
+                    image_dir: None,
+                    image_version: Some(123),
+                    image_semver: None,
+                    image_hash: None,
+                    channel: None,
+                    min_hwrev: None,
+                    update_status: Some(UpdateStatus::Updating),
+                    boot_attempts: None,
+                    test_window_secs: None,
+                    dry_run: None,
+                    staged_at: None,
+                    retry_backoff: None,
+                    unknown_fields: UnknownFields::EMPTY,
+                }
+            ))
+        );
+        assert_eq!(
+            passive_config(
+                "
+                [passive]
+                ready_for_update_flag=false
+                image_name=
+                image_version=none
+                update_status=none "
+            ),
+            Ok((
+                "",
+                PassiveConf {
+                    passive_config: ConfigKeys::Passive,
+                    ready_for_update_flag: false,
+                    image_name: None,
+                    image_dir: None,
+                    image_version: None,
+                    image_semver: None,
+                    image_hash: None,
+                    channel: None,
+                    min_hwrev: None,
+                    update_status: None,
+                    boot_attempts: None,
+                    test_window_secs: None,
+                    dry_run: None,
+                    staged_at: None,
+                    retry_backoff: None,
+                    unknown_fields: UnknownFields::EMPTY,
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_passive_conf_empty_name() {
+        assert_eq!(
+            passive_config(
+                "[passive]
+                ready_for_update_flag=false
+                image_name=
+                image_version=ver_34488735
+                update_status=updating
+                boot_attempts=1"
+            ),
+            Ok((
+                "",
+                PassiveConf {
+                    passive_config: ConfigKeys::Passive,
+                    ready_for_update_flag: false,
+                    image_name: None,
+                    image_dir: None,
+                    image_version: None,
+                    image_semver: None,
+                    image_hash: None,
+                    channel: None,
+                    min_hwrev: None,
+                    update_status: None,
+                    boot_attempts: Some(1),
+                    test_window_secs: None,
+                    dry_run: None,
+                    staged_at: None,
+                    retry_backoff: None,
+                    unknown_fields: UnknownFields::EMPTY,
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_passive_conf_version_none() {
+        assert_eq!(
+            passive_config(
+                "[passive]
+                ready_for_update_flag=false
+                image_name=xx.itb
+                image_version=none
+                update_status=updating
+                boot_attempts=0
+                "
+            ),
+            Ok((
+                "",
+                PassiveConf {
+                    passive_config: ConfigKeys::Passive,
+                    ready_for_update_flag: false,
+                    image_name: None,
+                    image_dir: None,
+                    image_version: None,
+                    image_semver: None,
+                    image_hash: None,
+                    channel: None,
+                    min_hwrev: None,
+                    update_status: None,
+                    boot_attempts: Some(0),
+                    test_window_secs: None,
+                    dry_run: None,
+                    staged_at: None,
+                    retry_backoff: None,
+                    unknown_fields: UnknownFields::EMPTY,
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_passive_conf_boot_attempts() {
+        assert_eq!(
+            passive_config(
+                "
+                [passive]
+                ready_for_update_flag=true
+                image_name=xx.itb
+                image_version=ver_123
+                update_status=testing
+                boot_attempts=3
+                "
+            ),
+            Ok((
+                "",
+                PassiveConf {
+                    passive_config: ConfigKeys::Passive,
+                    ready_for_update_flag: true,
+                    image_name: Some(("xx", ".itb")),
+                    image_dir: None,
+                    image_version: Some(123),
+                    image_semver: None,
+                    image_hash: None,
+                    channel: None,
+                    min_hwrev: None,
+                    update_status: Some(UpdateStatus::Testing),
+                    boot_attempts: Some(3),
+                    test_window_secs: None,
+                    dry_run: None,
+                    staged_at: None,
+                    retry_backoff: None,
+                    unknown_fields: UnknownFields::EMPTY,
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_passive_conf_unknown_keys() {
+        assert_eq!(
+            passive_config(
+                "[passive]
+                ready_for_update_flag=true
+                image_name=xx.itb
+                unknown_key=whatever
+                image_version=ver_123
+                signature = sha256:abcd # added by a newer writer
+                update_status=testing
+                boot_attempts=3
+                "
+            ),
+            Ok((
+                "",
+                PassiveConf {
+                    passive_config: ConfigKeys::Passive,
+                    ready_for_update_flag: true,
+                    image_name: Some(("xx", ".itb")),
+                    image_dir: None,
+                    image_version: Some(123),
+                    image_semver: None,
+                    image_hash: None,
+                    channel: None,
+                    min_hwrev: None,
+                    update_status: Some(UpdateStatus::Testing),
+                    boot_attempts: Some(3),
+                    test_window_secs: None,
+                    dry_run: None,
+                    staged_at: None,
+                    retry_backoff: None,
+                    unknown_fields: UnknownFields {
+                        text: "unknown_key=whatever\nsignature=sha256:abcd\n",
+                    },
+                }
+            ))
+        );
+        // unknown fields are kept, as written, and written back out after the known ones
+        let input = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n\n\
+                     [passive]\nready_for_update_flag=true\nnew_field = keepme # newer\n\
+                     boot_attempts=1\n";
+        let config = parse(input).unwrap();
+        let mut fields = config.passive().unknown_fields().iter();
+        assert_eq!(fields.next(), Some(("new_field", "keepme")));
+        assert_eq!(fields.next(), None);
+        assert_eq!(config.passive().unknown_keys(), 1);
+        let emitted = format!("{}", config);
+        assert!(emitted.ends_with("boot_attempts=1\nnew_field=keepme\n"));
+        assert_eq!(parse(&emitted), Ok(config.clone()));
+        #[cfg(feature = "alloc")]
+        assert_eq!(config.clone().into_owned().as_config(), config);
+        // ...but two sets of fields differ if their pairs do
+        let other = input.replace("keepme", "other");
+        assert_ne!(parse(&other).unwrap(), config);
+
+        // a malformed known key isn't skipped as unknown
+        assert_eq!(
+            passive_config("[passive]\nready_for_update_flag=true\nupdate_status=rebooting\n"),
+            Err(Err::Failure(ConfigError::InvalidStatus("rebooting")))
+        );
+    }
+
+    #[cfg(feature = "heapless")]
+    #[test]
+    fn test_parse_with_extra_keys() {
+        let input = "[active]
+            image_name=xx.itb
+            image_version=ver_1
+
+            [passive]
+            ready_for_update_flag=true
+            unknown_key=whatever
+            signature = sha256:abcd # added by a newer writer
+            update_status=testing
+            ";
+        let (config, extra_keys) = parse_with_extra_keys::<2>(input).unwrap();
+        assert_eq!(config, parse(input).unwrap());
+        assert_eq!(
+            extra_keys,
+            [("unknown_key", "whatever"), ("signature", "sha256:abcd")]
+        );
+        assert_eq!(
+            parse_with_extra_keys::<1>(input),
+            Err(ConfigError::TooManyExtraKeys)
+        );
+    }
+
+    #[test]
+    fn test_passive_conf_any_order() {
+        let expected = PassiveConf {
+            passive_config: ConfigKeys::Passive,
+            ready_for_update_flag: true,
+            image_name: Some(("xx", ".itb")),
+            image_dir: None,
+            image_version: Some(123),
+            image_semver: None,
+            image_hash: None,
+            channel: None,
+            min_hwrev: None,
+            update_status: Some(UpdateStatus::Testing),
+            boot_attempts: Some(2),
+            test_window_secs: None,
+            dry_run: None,
+            staged_at: None,
+            retry_backoff: None,
+            unknown_fields: UnknownFields::EMPTY,
+        };
+        for input in [
+            "[passive]\nready_for_update_flag=true\nimage_name=xx.itb\nimage_version=ver_123\nupdate_status=testing\nboot_attempts=2\n",
+            "[passive]\nready_for_update_flag=true\nupdate_status=testing\nimage_version=ver_123\nimage_name=xx.itb\nboot_attempts=2\n",
+            "[passive]\nready_for_update_flag=true\nboot_attempts=2\nimage_version=ver_123\nupdate_status=testing\nimage_name=xx.itb\n",
+        ] {
+            assert_eq!(passive_config(input), Ok(("", expected.clone())));
+        }
+        assert_eq!(
+            passive_config(
+                "[passive]\nready_for_update_flag=true\nupdate_status=testing\nimage_name=xx.itb\nupdate_status=success\n"
+            ),
+            Err(Err::Failure(ConfigError::DuplicateField(
+                "update_status=success\n"
+            )))
+        );
+        // ready_for_update_flag must still come first
+        assert!(
+            passive_config("[passive]\nimage_name=xx.itb\nready_for_update_flag=true\n").is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_with_duplicates() {
+        let input = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n# again\nimage_version=ver_9\n\
+                     channel=stable\n\
+                     [passive]\nready_for_update_flag=true\nimage_name=yy.itb\nimage_version=ver_2\n\
+                     update_status=testing\nimage_name=zz.itb\n\
+                     [settings]\nwatchdog_timeout=30\nwatchdog_timeout=60\n";
+        assert!(matches!(
+            parse(input),
+            Err(ConfigError::DuplicateField(i)) if i.starts_with("image_version=ver_9\n")
+        ));
+        assert_eq!(
+            parse_with_duplicates(input, DuplicateFields::default()),
+            parse(input)
+        );
+
+        let config = parse_with_duplicates(input, DuplicateFields::KeepFirst).unwrap();
+        assert_eq!(config.active().image_version(), 1);
+        // the fields after a skipped line are still read
+        assert_eq!(config.active().channel(), Some(Channel::Stable));
+        assert_eq!(config.passive().image_name(), Some(("yy", ".itb")));
+        assert_eq!(config.settings.unwrap().watchdog_timeout(), Some(30));
+
+        // without repeats, both policies agree
+        let input = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n\
+                     [passive]\nready_for_update_flag=false\n";
+        assert_eq!(
+            parse_with_duplicates(input, DuplicateFields::KeepFirst),
+            parse(input)
+        );
+        // a duplicate in a fixed-order section is reported as such
+        let input = "[active]\nimage_name=xx.itb\nimage_name=yy.itb\nimage_version=ver_1\n\
+                     [passive]\nready_for_update_flag=false\n";
+        assert!(matches!(
+            parse(input),
+            Err(ConfigError::DuplicateField(i)) if i.starts_with("image_name=yy.itb\n")
+        ));
+        assert_eq!(
+            parse_with_duplicates(input, DuplicateFields::KeepFirst)
+                .unwrap()
+                .active()
+                .image_name(),
+            ("xx", ".itb")
+        );
+    }
+
+    #[test]
+    fn test_lenient_status() {
+        let input = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n\
+                     [passive]\nready_for_update_flag=true\nimage_name=yy.itb\nimage_version=ver_2\n\
+                     update_status=quarantined\n";
+        assert!(parse(input).is_err());
+        assert_eq!(
+            parse_with_options(input, ParseOptions::default()),
+            parse(input)
+        );
+
+        let lenient = ParseOptions {
+            lenient_status: true,
+            ..ParseOptions::default()
+        };
+        let config = parse_with_options(input, lenient).unwrap();
+        assert_eq!(
+            config.passive().update_status(),
+            Some(UpdateStatus::Unknown("quarantined"))
+        );
+        // the unknown keyword is written back as it was read
+        let text = format!("{}", config);
+        assert!(text.contains("update_status=quarantined\n"));
+        assert_eq!(parse_with_options(&text, lenient), Ok(config.clone()));
+        #[cfg(feature = "alloc")]
+        assert_eq!(config.clone().into_owned().as_config(), config);
+        // an image in a state the bootloader doesn't understand isn't booted
+        assert_eq!(config.boot_target(), BootTarget::Active);
+        assert!(!UpdateStatus::Unknown("quarantined").can_transition_to(UpdateStatus::Testing));
+        assert!(UpdateStatus::Unknown("quarantined") < UpdateStatus::Failed);
+        // known statuses parse the same either way
+        let input = input.replace("quarantined", "testing");
+        assert_eq!(parse_with_options(&input, lenient), parse(&input));
+    }
+
+    #[test]
+    fn test_image_extensions() {
+        let input = "[active]\nimage_name=/boot/xx.fit\nimage_version=ver_1\n\
+                     [passive]\nready_for_update_flag=true\nimage_name=yy.uImage\n\
+                     image_version=ver_2\nupdate_status=testing\n\
+                     [recovery]\nimage_name=rescue.bin\nimage_version=ver_1\n";
+        assert!(parse(input).is_err());
+        assert_eq!(
+            parse_with_options(input, ParseOptions::default()),
+            parse(input)
+        );
+
+        let options = ParseOptions {
+            image_extensions: &[".itb", ".fit", ".bin", ".uImage"],
+            ..ParseOptions::default()
+        };
+        let config = parse_with_options(input, options).unwrap();
+        assert_eq!(config.active().image_format(), ImageFormat::Fit);
+        assert_eq!(config.active().image_dir(), Some("/boot/"));
+        assert_eq!(config.passive().image_format(), Some(ImageFormat::UImage));
+        assert_eq!(
+            config
+                .recovery
+                .as_ref()
+                .map(|recovery| recovery.image_name()),
+            Some(("rescue", ".bin"))
+        );
+        assert_eq!(
+            parse_with_options(&format!("{}", config), options),
+            Ok(config)
+        );
+        // the default still parses `.itb` files the same
+        let input = input
+            .replace(".fit", ".itb")
+            .replace(".uImage", ".itb")
+            .replace(".bin", ".itb");
+        assert_eq!(parse_with_options(&input, options), parse(&input));
+    }
+
+    #[test]
+    fn test_passive_conf_crlf() {
+        let lf = "[passive]\nready_for_update_flag=true\nimage_name=xx.itb\n\
+                  image_version=ver_123\nupdate_status=testing # staged\nboot_attempts=3\n";
+        let crlf = "[passive]\r\nready_for_update_flag=true\r\nimage_name=xx.itb\r\n\
+                    image_version=ver_123\r\nupdate_status=testing # staged\r\nboot_attempts=3\r\n";
+        let mixed = "[passive]\r\nready_for_update_flag=true\nimage_name=xx.itb\r\n\
+                     image_version=ver_123\nupdate_status=testing # staged\r\nboot_attempts=3";
+        let expected = passive_config(lf);
+        assert_eq!(
+            expected.as_ref().map(|(_, conf)| conf.update_status),
+            Ok(Some(UpdateStatus::Testing))
+        );
+        assert_eq!(passive_config(crlf), expected);
+        assert_eq!(passive_config(mixed), expected);
+        // An empty `image_name=` ends at the `\r\n` instead of swallowing the next line.
+        assert_eq!(
+            passive_config(
+                "[passive]\r\nready_for_update_flag=false\r\nimage_name=\r\nimage_version=none\r\n"
+            ),
+            Ok((
+                "",
+                PassiveConf {
+                    passive_config: ConfigKeys::Passive,
+                    ready_for_update_flag: false,
+                    image_name: None,
+                    image_dir: None,
+                    image_version: None,
+                    image_semver: None,
+                    image_hash: None,
+                    channel: None,
+                    min_hwrev: None,
+                    update_status: None,
+                    boot_attempts: None,
+                    test_window_secs: None,
+                    dry_run: None,
+                    staged_at: None,
+                    retry_backoff: None,
+                    unknown_fields: UnknownFields::EMPTY,
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_is_rollback_required() {
+        let (_, mut passive) = passive_config(
+            "[passive]
+            ready_for_update_flag=true
+            image_name=xx.itb
+            image_version=ver_123
+            update_status=testing
+            boot_attempts=3",
+        )
+        .unwrap();
+        assert!(passive.is_rollback_required(2));
+        assert!(passive.is_rollback_required(3));
+        assert!(!passive.is_rollback_required(4));
+
+        passive.boot_attempts = None;
+        assert!(!passive.is_rollback_required(0));
+    }
+
+    #[test]
+    fn test_accessors() {
+        let (_, (active, passive)) = parse_config(
+            "[active]
+            image_name=xx.itb
+            image_version=ver_1
+
+            [passive]
+            ready_for_update_flag=true
+            image_name=yy.itb
+            image_version=ver_2
+            update_status=testing",
+        )
+        .unwrap();
+        assert_eq!(active.image_name(), ("xx", ".itb"));
+        assert_eq!(active.image_version(), 1);
+        assert!(passive.ready_for_update_flag());
+        assert_eq!(passive.image_name(), Some(("yy", ".itb")));
+        assert_eq!(passive.image_version(), Some(2));
+        assert_eq!(passive.update_status(), Some(UpdateStatus::Testing));
+    }
+
+    #[test]
+    fn test_builders() {
+        let active = ActiveConf::new(("xx", ".itb"), 1);
+        let passive = PassiveConfBuilder::new()
+            .ready_for_update(true)
+            .image_name(("yy", ".itb"))
+            .image_version(2)
+            .update_status(UpdateStatus::Testing)
+            .build()
+            .unwrap();
+        let emitted = format!("{}\n{}", active, passive);
+        assert_eq!(parse_config(&emitted), Ok(("", (active, passive))));
+
+        assert_eq!(
+            PassiveConfBuilder::new().image_version(2).build(),
+            Err(ConfigError::MissingField("ready_for_update_flag"))
+        );
+        assert_eq!(
+            PassiveConfBuilder::new().ready_for_update(false).build(),
+            Ok(PassiveConf {
+                passive_config: ConfigKeys::Passive,
+                ready_for_update_flag: false,
+                image_name: None,
+                image_dir: None,
+                image_version: None,
+                image_semver: None,
+                image_hash: None,
+                channel: None,
+                min_hwrev: None,
+                update_status: None,
+                boot_attempts: None,
+                test_window_secs: None,
+                dry_run: None,
+                staged_at: None,
+                retry_backoff: None,
+                unknown_fields: UnknownFields::EMPTY,
+            })
+        );
+    }
+
+    #[test]
+    fn test_passive_slots() {
+        let input = "[active]
+            image_name=xx.itb
+            image_version=ver_1
+
+            [passive]
+            ready_for_update_flag=true
+            image_name=yy.itb
+            image_version=ver_2
+            update_status=testing
+
+            [passive]
+            ready_for_update_flag=false
+            image_name=zz.itb
+            image_version=ver_3
+            update_status=updating
+            ";
+        // without the feature, a second passive section is left over
+        #[cfg(not(feature = "multi-slot"))]
+        assert!(matches!(
+            parse(input),
+            Err(ConfigError::TrailingInput(rest)) if rest.starts_with("[passive]")
+        ));
+        #[cfg(feature = "multi-slot")]
+        {
+            let config = parse(input).unwrap();
+            let names: heapless::Vec<_, MAX_PASSIVE_SLOTS> = config
+                .passive_slots()
+                .map(|passive| passive.image_name)
+                .collect();
+            assert_eq!(names, [Some(("yy", ".itb")), Some(("zz", ".itb"))]);
+            assert_eq!(config.passive().image_version, Some(2));
+            assert_eq!(config.extra_passive[0].image_version, Some(3));
+            #[cfg(feature = "alloc")]
+            {
+                assert_eq!(
+                    Config::try_from(config.to_string().as_str()),
+                    Ok(config.clone())
+                );
+                assert_eq!(config.clone().into_owned().as_config(), config);
+            }
+            let too_many = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n[passive]\nready_for_update_flag=false\n[passive]\nready_for_update_flag=false\n[passive]\nready_for_update_flag=false\n[passive]\nready_for_update_flag=false\n[passive]\nready_for_update_flag=false\n";
+            assert!(matches!(
+                parse(too_many),
+                Err(ConfigError::DuplicateSection(header)) if header.starts_with("[passive]")
+            ));
+        }
+
+        let config = parse("[active]\nimage_name=xx.itb\nimage_version=ver_1\n[passive]\nready_for_update_flag=false\n").unwrap();
+        assert_eq!(config.passive_slots().count(), 1);
+    }
+
+    #[test]
+    fn test_boot_target() {
+        let config = |flag, version, status: Option<UpdateStatus<'static>>| {
+            let passive = PassiveConfBuilder::new()
+                .ready_for_update(flag)
+                .image_name(("yy", ".itb"))
+                .image_version(version);
+            Config {
+                format_version: 1,
+                board: None,
+                active: ActiveConf::new(("xx", ".itb"), 2),
+                passive: match status {
+                    Some(status) => passive.update_status(status),
+                    None => passive,
+                }
+                .build()
+                .unwrap(),
+                recovery: None,
+                settings: None,
+                #[cfg(feature = "multi-slot")]
+                extra_passive: heapless::Vec::new(),
+            }
+        };
+        use UpdateStatus::*;
+        assert_eq!(
+            config(true, 3, Some(Testing)).boot_target(),
+            BootTarget::Passive
+        );
+        assert_eq!(
+            config(true, 3, Some(Success)).boot_target(),
+            BootTarget::Passive
+        );
+        assert_eq!(config(true, 3, None).boot_target(), BootTarget::Passive);
+        // not ready
+        assert_eq!(
+            config(false, 3, Some(Testing)).boot_target(),
+            BootTarget::Active
+        );
+        // not newer
+        assert_eq!(
+            config(true, 2, Some(Testing)).boot_target(),
+            BootTarget::Active
+        );
+        assert_eq!(
+            config(true, 1, Some(Testing)).boot_target(),
+            BootTarget::Active
+        );
+        // failed
+        assert_eq!(
+            config(true, 3, Some(Failed)).boot_target(),
+            BootTarget::Active
+        );
+    }
+
+    #[test]
+    fn test_config_default_for() {
+        const DEFAULT: Config<'static> = Config::default_for(("signed-apertis-rpi4", ".itb"), 1);
+        assert_eq!(DEFAULT.format_version, FORMAT_VERSION);
+        assert_eq!(DEFAULT.active.image_name(), ("signed-apertis-rpi4", ".itb"));
+        assert_eq!(DEFAULT.active.image_version(), 1);
+        assert!(DEFAULT.passive.is_empty());
+        assert_eq!(DEFAULT.boot_target(), BootTarget::Active);
+
+        let text = format!("{}", DEFAULT);
+        assert_eq!(parse(&text), Ok(DEFAULT));
+    }
+
+    #[test]
+    fn test_promote() {
+        let staged = |status: &str| {
+            format!(
+                "[active]\nimage_name=xx.itb\nimage_version=ver_1\n\
+                 [passive]\nready_for_update_flag=true\nimage_name=yy.itb\n\
+                 image_version=ver_2.1.0\nupdate_status={}\n\
+                 [settings]\nwatchdog_timeout=30\n",
+                status
+            )
+        };
+        for status in ["success", "committed"] {
+            let input = staged(status);
+            let config = parse(&input).unwrap();
+            let promoted = config.promote().unwrap();
+            assert_eq!(promoted.active().image_name(), ("yy", ".itb"));
+            assert_eq!(promoted.active().image_version(), 2);
+            assert_eq!(
+                promoted.active().image_semver(),
+                ImageVersion {
+                    major: 2,
+                    minor: 1,
+                    patch: 0
+                }
+            );
+            assert!(promoted.passive().is_empty());
+            assert_eq!(
+                promoted.passive,
+                passive_config("[passive]\nready_for_update_flag=false\n")
+                    .unwrap()
+                    .1
+            );
+            assert_eq!(promoted.settings, config.settings);
+        }
+        for status in ["updating", "testing", "failed"] {
+            let input = staged(status);
+            let config = parse(&input).unwrap();
+            assert_eq!(
+                config.promote(),
+                Err(ConfigError::NotPromotable(config.passive.update_status))
+            );
+        }
+        // a dry run is never committed, even once it has succeeded
+        let input = staged("success").replace("[settings]", "dry_run=true\n[settings]");
+        let config = parse(&input).unwrap();
+        assert!(config.passive().is_dry_run());
+        assert_eq!(config.promote(), Err(ConfigError::DryRun));
+        let input = staged("success").replace("[settings]", "dry_run=false\n[settings]");
+        assert!(parse(&input).unwrap().promote().is_ok());
+        // a parsed passive slot has a status only alongside a name and version
+        let input = staged("success");
+        let mut unnamed = parse(&input).unwrap();
+        unnamed.passive = PassiveConfBuilder::new()
+            .ready_for_update(true)
+            .update_status(UpdateStatus::Success)
+            .build()
+            .unwrap();
+        assert_eq!(
+            unnamed.promote(),
+            Err(ConfigError::MissingField("image_name"))
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let before = parse(
+            "[active]
+            image_name=xx.itb
+            image_version=ver_1
+
+            [passive]
+            ready_for_update_flag=true
+            image_name=yy.itb
+            image_version=ver_2
+            update_status=testing",
+        )
+        .unwrap();
+        let mut after = before.clone();
+        after.passive.update_status = Some(UpdateStatus::Success);
+        assert_eq!(
+            diff(&before, &after),
+            ConfigDiff {
+                board: false,
+                active_name: false,
+                active_version: false,
+                active_hash: false,
+                active_channel: false,
+                active_min_hwrev: false,
+                passive_flag: false,
+                passive_name: false,
+                passive_version: false,
+                passive_hash: false,
+                passive_channel: false,
+                passive_min_hwrev: false,
+                passive_status: true,
+                boot_attempts: false,
+                test_window_secs: false,
+                dry_run: false,
+                staged_at: false,
+                retry_backoff: false,
+                recovery: false,
+                settings: false,
+            }
+        );
+        assert!(!diff(&before, &after).is_empty());
+        assert!(diff(&before, &before).is_empty());
+    }
+
+    #[test]
+    fn test_eq_ignoring_status() {
+        let input = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n\
+                     [passive]\nready_for_update_flag=true\nimage_name=yy.itb\nimage_version=ver_2\n\
+                     update_status=testing\n";
+        let before = parse(input).unwrap();
+        let success = input.replace("testing", "success");
+        let after = parse(&success).unwrap();
+        assert_ne!(before, after);
+        assert!(before.eq_ignoring_status(&after));
+        assert!(after.eq_ignoring_status(&before));
+        assert!(before.eq_ignoring_status(&before));
+
+        // any other change still counts
+        let bumped = input.replace("ver_2", "ver_3");
+        assert!(!before.eq_ignoring_status(&parse(&bumped).unwrap()));
+        let mut unflagged = after.clone();
+        unflagged.passive.ready_for_update_flag = false;
+        assert!(!before.eq_ignoring_status(&unflagged));
+    }
+
+    #[test]
+    fn test_passive_is_newer() {
+        let active = ActiveConf::new(("xx", ".itb"), 2);
+        let passive = |version| {
+            let builder = PassiveConfBuilder::new().ready_for_update(true);
+            match version {
+                Some(version) => builder.image_version(version),
+                None => builder,
+            }
+            .build()
+            .unwrap()
+        };
+        assert!(passive_is_newer(&active, &passive(Some(3))));
+        assert!(!passive_is_newer(&active, &passive(Some(2))));
+        assert!(!passive_is_newer(&active, &passive(Some(1))));
+        assert!(!passive_is_newer(&active, &passive(None)));
+
+        let (_, (active, passive)) = parse_config(
+            "[active]
+            image_name=xx.itb
+            image_version=ver_1.2.3
+
+            [passive]
+            ready_for_update_flag=true
+            image_name=xx.itb
+            image_version=ver_1.10.0
+            update_status=updating",
+        )
+        .unwrap();
+        assert!(passive_is_newer(&active, &passive));
+    }
+
+    #[test]
+    fn test_parse_config_semver() {
+        let (_, (active, passive)) = parse_config(
+            "[active]
+            image_name=xx.itb
+            image_version=ver_1.2.3
+
+            [passive]
+            ready_for_update_flag=true
+            image_name=xx.itb
+            image_version=ver_1.10.0
+            update_status=updating",
+        )
+        .unwrap();
+        assert_eq!(active.image_version(), 1);
+        assert!(passive.image_semver() > Some(active.image_semver()));
+    }
+
+    #[test]
+    fn test_parse_config_with_comments() {
+        let commented = parse_config(
+            "# generated by the update tooling
+            [active]
+            image_name=xx.itb
+            image_version=ver_34488734 # booted fine
+            # --------
+            [passive]
+            ready_for_update_flag=true # staged
+            image_name=xx.itb
+            # staged by the update daemon
+            image_version=ver_34488735 # bumped
+            update_status=updating
+            # end of file",
+        );
+        let plain = parse_config(
+            "[active]
+            image_name=xx.itb
+            image_version=ver_34488734
+
+            [passive]
+            ready_for_update_flag=true
+            image_name=xx.itb
+            image_version=ver_34488735
+            update_status=updating",
+        );
+        assert_eq!(commented, plain);
+        assert_eq!(commented.unwrap().0, "");
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let input = "[active]
+            image_name=xx.itb
+            image_version=ver_1.2.3
+
+            [passive]
+            ready_for_update_flag=true
+            image_name=yy.itb
+            image_version=ver_2
+            update_status=testing";
+        let (_, (active, passive)) = parse_config(input).unwrap();
+        assert_eq!(
+            format!("{}", active),
+            "[active]\nimage_name=xx.itb\nimage_version=ver_1.2.3\n"
+        );
+        assert_eq!(
+            format!("{}", passive),
+            "[passive]\nready_for_update_flag=true\nimage_name=yy.itb\nimage_version=ver_2\nupdate_status=testing\n"
+        );
+        let emitted = format!("{}\n{}", active, passive);
+        assert_eq!(parse_config(&emitted), Ok(("", (active, passive))));
+
+        // `None` fields are omitted rather than printed empty
+        let (_, (_, passive)) = parse_config(
+            "[active]
+            image_name=xx.itb
+            image_version=ver_1
+
+            [passive]
+            ready_for_update_flag=false
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            format!("{}", passive),
+            "[passive]\nready_for_update_flag=false\n"
+        );
+
+        // fields written by a newer tool survive parse, mutate, write back
+        let input = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n\n\
+                     [passive]\nready_for_update_flag=false\nsignature=sha256:abcd\n\
+                     boot_attempts=2\n";
+        let mut config = parse(input).unwrap();
+        config.passive.set_ready_for_update(true);
+        let emitted = format!("{}", config);
+        assert_eq!(
+            emitted,
+            "[active]\nimage_name=xx.itb\nimage_version=ver_1\n\n\
+             [passive]\nready_for_update_flag=true\nboot_attempts=2\nsignature=sha256:abcd\n"
+        );
+        assert_eq!(parse(&emitted), Ok(config));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_config_to_string() {
+        let input = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n\n[passive]\nready_for_update_flag=false\n";
+        let (_, (active, passive)) = parse_config(input).unwrap();
+        assert_eq!(config_to_string(&active, &passive), input);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_expand_vars() {
+        let input = "[active]\nimage_name=${BOARD}.itb\nimage_version=ver_1\n\
+                     [passive]\nready_for_update_flag=false\n";
+        let resolver = |name: &str| (name == "BOARD").then_some("rpi4");
+        let expanded = expand_vars(input, resolver).unwrap();
+        assert_eq!(expanded, input.replace("${BOARD}", "rpi4"));
+        let config = parse(&expanded).unwrap();
+        assert_eq!(config.active().image_name(), ("rpi4", ".itb"));
+
+        // nothing to expand, nothing copied
+        let plain = input.replace("${BOARD}", "xx");
+        assert!(matches!(
+            expand_vars(&plain, resolver),
+            Ok(Cow::Borrowed(_))
+        ));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_expand_vars_undefined() {
+        let input = "[active]\nimage_name=${BOARD}-${SOC}.itb\nimage_version=ver_1\n";
+        let resolver = |name: &str| (name == "BOARD").then_some("rpi4");
+        assert_eq!(
+            expand_vars(input, resolver),
+            Err(ConfigError::UndefinedVariable("SOC"))
+        );
+        // an unclosed `${` can't be expanded either
+        assert_eq!(
+            expand_vars("image_name=${BOARD.itb\n}", resolver),
+            Err(ConfigError::UndefinedVariable("${BOARD.itb\n}"))
+        );
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_into_owned() {
+        let owned = {
+            let buffer = String::from(
+                "[active]\nimage_name=xx.itb\nimage_version=ver_1.2.3\n\n\
+                 [passive]\nready_for_update_flag=true\nimage_name=yy.itb\n\
+                 image_version=ver_2\nupdate_status=testing\n",
+            );
+            parse(&buffer).unwrap().into_owned()
+        };
+        let config = owned.as_config();
+        assert_eq!(config.active().image_name(), ("xx", ".itb"));
+        assert_eq!(config.active().image_semver().to_string(), "1.2.3");
+        assert_eq!(config.passive().image_name(), Some(("yy", ".itb")));
+        assert_eq!(
+            config.passive().update_status(),
+            Some(UpdateStatus::Testing)
+        );
+        assert_eq!(
+            config_to_string(config.active(), config.passive()),
+            "[active]\nimage_name=xx.itb\nimage_version=ver_1.2.3\n\n\
+             [passive]\nready_for_update_flag=true\nimage_name=yy.itb\n\
+             image_version=ver_2\nupdate_status=testing\n"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let input = "[active]
+            image_name=xx.itb
+            image_version=ver_1.2.3
+
+            [passive]
+            ready_for_update_flag=true
+            image_name=yy.itb
+            image_version=ver_2
+            update_status=testing";
+        let config = parse(input).unwrap();
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(json.contains(r#""image_name":{"name":"xx","ext":".itb"}"#));
+        assert!(json.contains(r#""update_status":"testing""#));
+        assert_eq!(serde_json::from_str::<Config>(&json).unwrap(), config);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_signature_block() {
+        let input = "[signature]\nalg=ed25519\nsig=SGVsbG8sIHdvcmxkIQ==\n";
+        let (_, signature) = signature_block(input).unwrap();
+        let json = serde_json::to_string(&signature).unwrap();
+        assert_eq!(
+            serde_json::from_str::<SignatureBlock>(&json).unwrap(),
+            signature
+        );
+        // deserializing checks the signature like the parser does
+        for bad in ["====", "A===", "SGVsbG8*", ""] {
+            let json = format!(r#"{{"alg":"ed25519","sig":"{}"}}"#, bad);
+            assert!(serde_json::from_str::<SignatureBlock>(&json).is_err());
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_set_ready_for_update() {
+        let input = "[active]
+            image_name=xx.itb
+            image_version=ver_1.2.3
+
+            [passive]
+            ready_for_update_flag=false
+            image_name=yy.itb
+            image_version=ver_2
+            update_status=updating
+            boot_attempts=0
+
+            [settings]
+            auto_rollback=true";
+        let mut config = parse(input).unwrap();
+        config.passive.set_ready_for_update(true);
+        assert!(config.passive().ready_for_update_flag());
+        let emitted = config.to_string();
+        assert!(emitted.contains("ready_for_update_flag=true\n"));
+        assert_eq!(parse(&emitted), Ok(config));
+    }
+
+    #[test]
+    fn test_parse() {
+        let input = "[active]
+            image_name=xx.itb
+            image_version=ver_1
+
+            [passive]
+            ready_for_update_flag=true
+            image_name=yy.itb
+            image_version=ver_2
+            update_status=testing";
+        let config = parse(input).unwrap();
+        assert_eq!(config.active().image_version(), 1);
+        assert_eq!(config.passive().image_version(), Some(2));
+        assert_eq!(
+            parse_config(input),
+            Ok(("", (config.active, config.passive)))
+        );
+    }
+
+    #[test]
+    fn test_settings_conf() {
+        assert_eq!(
+            settings_config(
+                "[settings]
+                watchdog_timeout=30
+                auto_rollback=TRUE
+                "
+            ),
+            Ok((
+                "",
+                SettingsConf {
+                    settings_config: ConfigKeys::Settings,
+                    watchdog_timeout: Some(30),
+                    auto_rollback: Some(true)
+                }
+            ))
+        );
+        assert_eq!(
+            settings_config("[settings]\n"),
+            Ok((
+                "",
+                SettingsConf {
+                    settings_config: ConfigKeys::Settings,
+                    watchdog_timeout: None,
+                    auto_rollback: None
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_with_settings() {
+        let input = "[settings]
+            watchdog_timeout=30
+            auto_rollback=false
+
+            [active]
+            image_name=xx.itb
+            image_version=ver_1
+
+            [passive]
+            ready_for_update_flag=true
+            image_name=yy.itb
+            image_version=ver_2
+            update_status=testing";
+        let config = parse(input).unwrap();
+        let settings = config.settings.as_ref().unwrap();
+        assert_eq!(settings.watchdog_timeout(), Some(30));
+        assert_eq!(settings.auto_rollback(), Some(false));
+        assert_eq!(config.passive().image_version(), Some(2));
+        assert_eq!(
+            format!("{}", settings),
+            "[settings]\nwatchdog_timeout=30\nauto_rollback=false\n"
+        );
+
+        // the settings section may come last, and is `None` when absent
+        let trailing = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n\
+                        [passive]\nready_for_update_flag=true\n\
+                        [settings]\nwatchdog_timeout=30\n";
+        assert_eq!(
+            parse(trailing)
+                .unwrap()
+                .settings
+                .unwrap()
+                .watchdog_timeout(),
+            Some(30)
+        );
+        assert_eq!(
+            parse("[active]\nimage_name=xx.itb\nimage_version=ver_1\n[passive]\nready_for_update_flag=true\n")
+                .unwrap()
+                .settings,
+            None
+        );
+        assert_eq!(
+            parse("[settings]\n[active]\nimage_name=xx.itb\nimage_version=ver_1\n[settings]\n"),
+            Err(ConfigError::DuplicateSection("[settings]\n"))
+        );
+    }
+
+    #[test]
+    fn test_parse_with_recovery() {
+        let input = "[active]
+            image_name=xx.itb
+            image_version=ver_1
+
+            [passive]
+            ready_for_update_flag=true
+            image_name=yy.itb
+            image_version=ver_2
+            update_status=testing
+
+            [recovery]
+            image_name=rescue.itb
+            image_version=ver_1.0.3
+            ";
+        let config = parse(input).unwrap();
+        let recovery = config.recovery.as_ref().unwrap();
+        assert_eq!(recovery.image_name(), ("rescue", ".itb"));
+        assert_eq!(recovery.image_version(), 1);
+        assert_eq!(
+            recovery.image_semver(),
+            ImageVersion {
+                major: 1,
+                minor: 0,
+                patch: 3
+            }
+        );
+        assert_eq!(recovery.image_hash(), None);
+        assert_eq!(config.passive().image_version(), Some(2));
+        assert_eq!(
+            format!("{}", recovery),
+            "[recovery]\nimage_name=rescue.itb\nimage_version=ver_1.0.3\n"
+        );
+        #[cfg(feature = "alloc")]
+        {
+            assert_eq!(parse(&config.to_string()), Ok(config.clone()));
+            assert_eq!(config.clone().into_owned().as_config(), config);
+        }
+
+        assert_eq!(recovery.min_hwrev(), None);
+        assert!(recovery.allows_hwrev(0));
+        let input = input.replace("ver_1.0.3\n", "ver_1.0.3\n            min_hwrev=2\n");
+        let config = parse(&input).unwrap();
+        let recovery = config.recovery.as_ref().unwrap();
+        assert_eq!(recovery.min_hwrev(), Some(2));
+        assert!(!recovery.allows_hwrev(1));
+        assert!(recovery.allows_hwrev(2));
+        assert_eq!(
+            format!("{}", recovery),
+            "[recovery]\nimage_name=rescue.itb\nimage_version=ver_1.0.3\nmin_hwrev=2\n"
+        );
+        assert_eq!(parse(&format!("{}", config)), Ok(config.clone()));
+        #[cfg(feature = "alloc")]
+        assert_eq!(config.clone().into_owned().as_config(), config);
+
+        assert_eq!(
+            recovery_config("[recovery]\nimage_name=rescue.itb\n"),
+            Err(Err::Error(ConfigError::MissingField("image_version")))
+        );
+        // the recovery section is optional and may come first
+        assert_eq!(
+            parse("[active]\nimage_name=xx.itb\nimage_version=ver_1\n[passive]\nready_for_update_flag=true\n")
+                .unwrap()
+                .recovery,
+            None
+        );
+        let first = "[recovery]\nimage_name=rescue.itb\nimage_version=ver_1\n\
+                     [active]\nimage_name=xx.itb\nimage_version=ver_1\n\
+                     [passive]\nready_for_update_flag=true\n";
+        assert!(parse(first).unwrap().recovery.is_some());
+        assert_eq!(config_keys("[recovery]"), Ok(("", ConfigKeys::Recovery)));
+    }
+
+    #[test]
+    fn test_parse_with_board() {
+        assert_eq!(
+            board_config("[board]\nmodel=imx8m-evk\nhwrev=3\n"),
+            Ok((
+                "",
+                BoardConf {
+                    board_config: ConfigKeys::Board,
+                    model: "imx8m-evk",
+                    hwrev: 3,
+                }
+            ))
+        );
+        assert_eq!(
+            board_config("[board]\nhwrev=3\n"),
+            Err(Err::Error(ConfigError::MissingField("model")))
+        );
+        let body = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n\
+                    [passive]\nready_for_update_flag=true\n";
+        // older two-section files have no board
+        assert_eq!(parse(body).unwrap().board, None);
+        let input = format!("version=1\n[board]\nmodel=imx8m-evk\nhwrev=3\n{}", body);
+        let config = parse(&input).unwrap();
+        let board = config.board.as_ref().unwrap();
+        assert_eq!((board.model(), board.hwrev()), ("imx8m-evk", 3));
+        assert_eq!(parse(&format!("{}", config)).unwrap(), config);
+
+        // the board section can only lead
+        let late = format!("{}[board]\nmodel=imx8m-evk\nhwrev=3\n", body);
+        assert!(matches!(
+            parse(&late),
+            Err(ConfigError::UnknownSection(rest)) if rest.starts_with("[board]")
+        ));
+        let twice = format!(
+            "[board]\nmodel=a\nhwrev=1\n[board]\nmodel=b\nhwrev=2\n{}",
+            body
+        );
+        assert!(matches!(
+            parse(&twice),
+            Err(ConfigError::DuplicateSection(rest)) if rest.starts_with("[board]\nmodel=b")
+        ));
+    }
+
+    #[test]
+    fn test_parse_signed() {
+        let config = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n\
+                      [passive]\nready_for_update_flag=false\n\n";
+        let input = format!(
+            "{}[signature]\nalg=ed25519\nsig=SGVsbG8sIHdvcmxkIQ==\n",
+            config
+        );
+        let (parsed, signature, signed) = parse_signed(&input).unwrap();
+        assert_eq!(parsed, parse(config).unwrap());
+        assert_eq!(signed, config);
+        assert_eq!(signature.alg(), "ed25519");
+        assert_eq!(signature.sig_base64(), "SGVsbG8sIHdvcmxkIQ==");
+        #[cfg(feature = "alloc")]
+        {
+            assert_eq!(signature.sig().unwrap(), b"Hello, world!");
+            let sig = SignatureBlock {
+                alg: "ed25519",
+                sig: "TWFu",
+            };
+            assert_eq!(sig.sig().unwrap(), b"Man");
+            // only a block built around the parser could hold a bad signature
+            for bad in ["====", "A===", "TW*u", ""] {
+                let sig = SignatureBlock {
+                    alg: "ed25519",
+                    sig: bad,
+                };
+                assert_eq!(sig.sig(), Err(ConfigError::InvalidSignature(bad)));
+            }
+        }
+
+        assert_eq!(
+            signature_block("[signature]\nalg=ed25519\nsig=SGVsbG8*\n"),
+            Err(Err::Failure(ConfigError::InvalidSignature("SGVsbG8*")))
+        );
+        assert_eq!(
+            signature_block("[signature]\nalg=ed25519\nsig=SGVsbG8\n"),
+            Err(Err::Failure(ConfigError::InvalidSignature("SGVsbG8")))
+        );
+        assert_eq!(
+            signature_block("[signature]\nsig=SGVsbG8sIHdvcmxkIQ==\n"),
+            Err(Err::Error(ConfigError::MissingField(ALG_KEY)))
+        );
+        // an unsigned config has nothing after it
+        assert!(parse_signed(config).is_err());
+    }
+
+    #[test]
+    fn test_signed_region() {
+        let config = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n\
+                      [passive]\nready_for_update_flag=false\n\n";
+        let input = format!(
+            "{}  [signature]  # ed25519\nalg=ed25519\nsig=TWFu\n",
+            config
+        );
+        let region = signed_region(&input).unwrap();
+        assert_eq!(region, format!("{}  ", config));
+        assert!(input[region.len()..].starts_with(SIGNATURE_HEADER));
+        assert_eq!(parse_signed(&input).unwrap().2, region);
+
+        assert_eq!(signed_region(config), None);
+        // only a real header counts, not a section that merely starts with the same name
+        assert_eq!(signed_region("[signatures]\nalg=ed25519\n"), None);
+        assert_eq!(signed_region("[signature]"), Some(""));
+    }
+
+    #[test]
+    fn test_format_version() {
+        let body = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n\
+                    [passive]\nready_for_update_flag=false\n";
+        assert_eq!(parse(body).unwrap().format_version, DEFAULT_FORMAT_VERSION);
+        // the default version isn't written back out
+        assert!(format!("{}", parse(body).unwrap()).starts_with("[active]"));
+        let versioned = format!("# written by the updater\nversion = 1\n{}", body);
+        assert_eq!(parse(&versioned).unwrap().format_version, 1);
+        assert_eq!(
+            parse(&versioned).unwrap().active,
+            parse(body).unwrap().active
+        );
+        assert_eq!(
+            parse(&format!("version=2\n{}", body)),
+            Err(ConfigError::UnsupportedFormatVersion("2"))
+        );
+        assert_eq!(
+            parse(&format!("version=0\n{}", body)),
+            Err(ConfigError::UnsupportedFormatVersion("0"))
+        );
+        assert_eq!(
+            parse(&format!("version=99999999999\n{}", body)),
+            Err(ConfigError::UnsupportedFormatVersion("99999999999"))
+        );
+        assert_eq!(
+            format_version::<ConfigError>("[active]"),
+            Ok(("[active]", 1))
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let input = "[active]
+            image_name=xx.itb
+            image_version=ver_1
+
+            [passive]
+            ready_for_update_flag=true
+            image_name=yy.itb
+            image_version=ver_2
+            update_status=testing";
+        assert_eq!(parse(input).unwrap().validate(), Ok(()));
+        assert_eq!(
+            parse(&input.replace("yy", "xx")).unwrap().validate(),
+            Err(ConfigError::DuplicateImageName("xx"))
+        );
+        // an empty passive slot never clashes
+        assert_eq!(
+            parse("[active]\nimage_name=xx.itb\nimage_version=ver_1\n[passive]\nready_for_update_flag=false\n")
+                .unwrap()
+                .validate(),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse(
+                "[active]
+                image_name=xx.itb
+                image_version=ver_1
+
+                [staging]
+                ready_for_update_flag=true"
+            ),
+            Err(ConfigError::UnknownSection("[staging]"))
+        );
+        assert_eq!(
+            parse(
+                "[active]
+                image_version=ver_1
+
+                [passive]
+                ready_for_update_flag=true"
+            ),
+            Err(ConfigError::MissingField("image_name"))
+        );
+        assert_eq!(
+            parse(
+                "[active]
+                image_name=xx.itb
+                image_version=ver_1.99999999999.0
+
+                [passive]
+                ready_for_update_flag=true"
+            ),
+            Err(ConfigError::InvalidVersion("99999999999"))
+        );
+        assert_eq!(
+            parse(
+                "[active]
+                image_name=xx.itb
+                image_version=ver_1
+
+                [passive]
+                ready_for_update_flag=true
+                image_name=xx.itb
+                image_version=ver_99999999999999999999
+                update_status=updating"
+            ),
+            Err(ConfigError::InvalidVersion("99999999999999999999"))
+        );
+        assert_eq!(
+            parse(
+                "[active]
+                image_name=xx.itb
+                image_version=ver_1
+
+                [passive]
+                ready_for_update_flag=true
+                update_status=rebooting"
+            ),
+            Err(ConfigError::InvalidStatus("rebooting"))
+        );
+    }
+
+    #[test]
+    fn test_config_error_from_nom() {
+        assert_eq!(
+            ConfigError::from(Err::Failure(ConfigError::MissingField("image_name"))),
+            ConfigError::MissingField("image_name")
+        );
+        assert_eq!(
+            ConfigError::from(Err::Error(Error::new("[]", ErrorKind::Tag))),
+            ConfigError::Parse(Error::new("[]", ErrorKind::Tag))
+        );
+        assert_eq!(
+            ConfigError::from(Err::<Error<&str>>::Incomplete(nom::Needed::Unknown)),
+            ConfigError::Incomplete
+        );
+    }
+
+    #[test]
+    fn test_config_error_display() {
+        assert_eq!(
+            format!("{}", ConfigError::UnknownSection("[foo]")),
+            "unknown section: [foo]"
+        );
+        assert_eq!(
+            format!("{}", ConfigError::InvalidVersion("")),
+            "invalid version number"
+        );
+        assert_eq!(
+            format!("{}", ConfigError::MissingField(IMAGE_NAME_KEY)),
+            "missing field: image_name"
+        );
+        // only the offending line is shown, not the rest of the input
+        assert_eq!(
+            format!(
+                "{}",
+                ConfigError::DuplicateSection("[settings]\nwatchdog_timeout=5\n")
+            ),
+            "duplicate section: [settings]"
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                ConfigError::NotPromotable(Some(UpdateStatus::Testing))
+            ),
+            "passive image can't be promoted from status testing"
+        );
+        let error = parse(
+            "[active]\nimage_name=xx.itb\nimage_version=ver_x\n\
+             [passive]\nready_for_update_flag=false\n",
+        )
+        .unwrap_err();
+        assert_eq!(format!("{}", error), "invalid version number: x");
+
+        #[cfg(feature = "std")]
+        {
+            let error: &dyn std::error::Error = &error;
+            assert_eq!(format!("{}", error), "invalid version number: x");
+        }
+    }
+
+    #[test]
+    fn test_parse_section_order() {
+        let active_first = parse(
+            "[active]
+            image_name=xx.itb
+            image_version=ver_1
+
+            [passive]
+            ready_for_update_flag=true
+            image_name=yy.itb
+            image_version=ver_2
+            update_status=testing",
+        );
+        let passive_first = parse(
+            "[passive]
+            ready_for_update_flag=true
+            image_name=yy.itb
+            image_version=ver_2
+            update_status=testing
+
+            [active]
+            image_name=xx.itb
+            image_version=ver_1
+            ",
+        );
+        assert!(active_first.is_ok());
+        assert_eq!(active_first, passive_first);
+
+        let duplicate = "[active]
+            image_name=xx.itb
+            image_version=ver_1
+
+            [active]
+            image_name=yy.itb
+            image_version=ver_2
+            ";
+        assert_eq!(
+            parse(duplicate),
+            Err(ConfigError::DuplicateSection(
+                "[active]
+            image_name=yy.itb
+            image_version=ver_2
+            "
+            ))
+        );
+        assert!(parse_config(duplicate).is_err());
+        assert_eq!(
+            active_config("[passive]\nimage_name=xx.itb\nimage_version=ver_1 "),
+            Err(Err::Error(ConfigError::Parse(Error::new(
+                "[passive]\nimage_name=xx.itb\nimage_version=ver_1 ",
+                ErrorKind::Verify
+            ))))
+        );
+    }
+
+    #[test]
+    fn test_parse_missing_passive_header() {
+        let orphan = "ready_for_update_flag=true\nimage_name=yy.itb\nimage_version=ver_2\n\
+                      update_status=testing\n";
+        let input = format!(
+            "[active]\nimage_name=xx.itb\nimage_version=ver_1\n\n{}",
+            orphan
+        );
+        assert_eq!(parse(&input), Err(ConfigError::MissingSection(orphan)));
+        assert_eq!(
+            format!("{}", ConfigError::MissingSection(orphan)),
+            "key outside its section: ready_for_update_flag=true"
+        );
+        assert_eq!(parse_config_located(&input).unwrap_err().line, 5);
+        // also after another section, or with the passive-only keys in a different order
+        let input = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n\
+                     [settings]\nwatchdog_timeout=30\n# staged\nupdate_status=testing\n";
+        assert_eq!(
+            parse(input),
+            Err(ConfigError::MissingSection("update_status=testing\n"))
+        );
+        // with the header in place the same keys are fine
+        let input = format!(
+            "[active]\nimage_name=xx.itb\nimage_version=ver_1\n[passive]\n{}",
+            orphan
+        );
+        assert!(parse(&input).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_str() {
+        let input = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n\n\
+                     [passive]\nready_for_update_flag=false\n";
+        assert_eq!(Config::try_from(input), parse(input));
+        assert_eq!(
+            ActiveConf::try_from("[active]\nimage_name=xx.itb\nimage_version=ver_1\n"),
+            Ok(ActiveConf::new(("xx", ".itb"), 1))
+        );
+        assert_eq!(
+            PassiveConf::try_from("[passive]\nready_for_update_flag=false\n"),
+            PassiveConfBuilder::new().ready_for_update(false).build()
+        );
+        // the whole input must be consumed
+        assert_eq!(
+            ActiveConf::try_from("[active]\nimage_name=xx.itb\nimage_version=ver_1\nextra"),
+            Err(ConfigError::TrailingInput("extra"))
+        );
+        assert_eq!(
+            PassiveConf::try_from("[passive]\nready_for_update_flag=false\n[active]"),
+            Err(ConfigError::TrailingInput("[active]"))
+        );
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_owned_config_from_str() {
+        let input = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n\n\
+                     [passive]\nready_for_update_flag=true\nimage_name=yy.itb\n\
+                     image_version=ver_2\nupdate_status=testing\n";
+        let owned: OwnedConfig = input.parse().unwrap();
+        assert_eq!(owned, parse(input).unwrap().into_owned());
+        // the result outlives its input
+        let owned = {
+            let text = String::from(input);
+            text.parse::<OwnedConfig>().unwrap()
+        };
+        assert_eq!(owned.as_config(), parse(input).unwrap());
+
+        let bad = input.replace("testing", "rebooting");
+        let error = bad.parse::<OwnedConfig>().unwrap_err();
+        assert_eq!(
+            error,
+            OwnedConfigError {
+                message: ConfigError::InvalidStatus("rebooting").to_string(),
+                line: 9,
+                column: 15
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            format!("9:15: {}", ConfigError::InvalidStatus("rebooting"))
+        );
+        // as strict as `parse`
+        let trailing = format!("{}garbage", input);
+        assert!(parse(&trailing).is_err());
+        assert!(trailing.parse::<OwnedConfig>().is_err());
+    }
+
+    #[test]
+    fn test_parse_config_strict() {
+        let input = "[active]
+            image_name=xx.itb
+            image_version=ver_1
 
-fn image_version(input: &str) -> IResult<&str, u32> {
-    preceded(
-        tag("image_version="),
-        separated_pair(tag("ver"), tag("_"), tuple((digit0, multispace1))),
-    )(input)
-    .map(|(next_input, res)| {
-        (
-            next_input,
-            res.1 .0.parse::<u32>().expect("not a valid version number"),
-        )
-    })
-}
+            [passive]
+            ready_for_update_flag=true
+            image_name=yy.itb
+            image_version=ver_2
+            update_status=testing
+            # trailing comment
+            ";
+        let (_, expected) = parse_config(input).unwrap();
+        assert_eq!(parse_config_strict(input), Ok(expected));
 
-fn update_status(input: &str) -> IResult<&str, UpdateStatus> {
-    preceded(
-        tag("update_status="),
-        alt((tag("updating"), tag("testing"), tag("success"))),
-    )(input)
-    .map(|(next_input, res)| (next_input, res.into()))
-}
+        let garbage = "[active]
+            image_name=xx.itb
+            image_version=ver_1
 
-fn ready_for_update(input: &str) -> IResult<&str, bool> {
-    preceded(
-        tag("ready_for_update_flag="),
-        alt((tag("true"), tag("false"))),
-    )(input)
-    .map(|(next_input, res)| {
-        (
-            next_input,
-            bool::from_str(res).expect("not a boolean value"),
-        )
-    })
-}
+            [passive]
+            ready_for_update_flag=true
+            image_name=yy.itb
+            image_version=ver_2
+            update_status=testing
+            garbage";
+        assert!(parse_config(garbage).is_ok());
+        assert_eq!(
+            parse_config_strict(garbage),
+            Err(ConfigError::TrailingInput("garbage"))
+        );
+    }
 
-fn active_config(input: &str) -> IResult<&str, ActiveConf> {
-    tuple((
-        multispace0,
-        config_keys,
-        multispace1,
-        image_name,
-        multispace1,
-        image_version,
-        // multispace1,
-    ))(input)
-    .map(|(next_input, res)| {
-        let (_crlf0, active_config, _crlf1, image_name, _crlf2, image_version) = res;
-        (
-            next_input,
-            ActiveConf {
-                active_config,
-                image_name,
-                image_version,
-            },
-        )
-    })
-}
+    #[test]
+    fn test_is_plausible_config() {
+        const EMBEDDED: &str = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n\
+                                [passive]\nready_for_update_flag=false\n";
+        const _: () = assert!(is_plausible_config(EMBEDDED));
+        assert!(parse(EMBEDDED).is_ok());
+        // a typo'd header or key is caught
+        assert!(!is_plausible_config(
+            &EMBEDDED.replace("[passive]", "[pasive]")
+        ));
+        assert!(!is_plausible_config(
+            &EMBEDDED.replace("ready_for", "ready-for")
+        ));
+        assert!(!is_plausible_config(""));
+        // but values aren't checked
+        assert!(is_plausible_config(&EMBEDDED.replace("ver_1", "ver_x")));
+        assert_eq!(ConfigKeys::Passive.header(), PASSIVE_HEADER);
+    }
 
-fn passive_config(input: &str) -> IResult<&str, PassiveConf> {
-    tuple((
-        multispace0,
-        config_keys,
-        multispace1,
-        ready_for_update,
-        multispace1,
-        opt(image_name),
-        multispace0,
-        opt(image_version),
-        // multispace1,
-        opt(update_status),
-        multispace0,
-    ))(input)
-    .map(|(next_input, res)| {
-        let (
-            _crlf0,
-            passive_config,
-            _crlf1,
-            ready_for_update_flag,
-            _crlf2,
-            mut image_name,
-            _crlf3,
-            mut image_version,
-            mut update_status,
-            _crlf5,
-        ) = res;
-
-        match (image_name, image_version, &update_status) {
-            (None, _, _) => (image_version, update_status) = (None, None),
-            (_, None, _) => (image_name, update_status) = (None, None),
-            (_, _, &None) => (image_name, image_version) = (None, None),
-            (_, _, _) => {}
+    #[test]
+    fn test_canonicalize() {
+        let messy = "  # staged by the updater
+            [passive]
+            ready_for_update_flag = TRUE
+            update_status=Testing   # first boot
+            image_version = ver_0x2
+            image_name = yy.itb
+            some_new_key = 1\r
+            [active]\r
+            image_name=xx.itb\r
+            image_version=ver_1\r
+            ";
+        let tidy = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n\n\
+                    [passive]\nready_for_update_flag=true\nimage_name=yy.itb\n\
+                    image_version=ver_2\nupdate_status=testing\n";
+        let mut buf = [0u8; 256];
+        assert_eq!(canonicalize_into(messy, &mut buf), Ok(tidy));
+        let mut again = [0u8; 256];
+        assert_eq!(canonicalize_into(tidy, &mut again), Ok(tidy));
+        assert_eq!(
+            canonicalize_into(messy, &mut buf[..tidy.len() - 1]),
+            Err(ConfigError::BufferFull)
+        );
+        assert_eq!(
+            canonicalize_into("[active]\n", &mut buf),
+            Err(ConfigError::MissingField(IMAGE_NAME_KEY))
+        );
+        #[cfg(feature = "alloc")]
+        {
+            assert_eq!(canonicalize(messy).as_deref(), Ok(tidy));
+            assert_eq!(canonicalize(tidy).as_deref(), Ok(tidy));
         }
-        (
-            next_input,
-            PassiveConf {
-                passive_config,
-                ready_for_update_flag,
-                image_name,
-                image_version,
-                update_status,
-            },
-        )
-    })
-}
+    }
 
-/// Parses the provided configuration file and returns the active and passive components
-/// as a tuple. A valid config file must contain an active and a passive component.
-/// [`parse_config`] assumes the provided config (always) includes the active and
-/// passive components. The passive componets may contain optional fields such `image_name`,
-/// `image_version` and `update_status`
-///
-/// **note:** for an example of what constitutes a `valid config file`, please see `update_conf.txt`
-pub fn parse_config(input: &str) -> IResult<&str, (ActiveConf, PassiveConf)> {
-    tuple((active_config, passive_config))(input)
-}
+    #[test]
+    fn test_semantic_hash() {
+        // FNV-1a, to keep the test free of std
+        struct Fnv(u64);
+        impl core::hash::Hasher for Fnv {
+            fn finish(&self) -> u64 {
+                self.0
+            }
+            fn write(&mut self, bytes: &[u8]) {
+                for byte in bytes {
+                    self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3);
+                }
+            }
+        }
+        let hash = |input| {
+            let mut hasher = Fnv(0xcbf29ce484222325);
+            parse(input).unwrap().semantic_hash(&mut hasher);
+            core::hash::Hasher::finish(&hasher)
+        };
+        let flat = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n\
+                    [passive]\nready_for_update_flag=true\nimage_name=yy.itb\n\
+                    image_version=ver_2\nupdate_status=testing\n";
+        let indented = "[active]
+                image_name = xx.itb
+                image_version = ver_1
+            [passive]
+                ready_for_update_flag = true   # staged
+                image_name = yy.itb
+                image_version = ver_2
+                update_status = testing
+            ";
+        assert_eq!(hash(flat), hash(indented));
+        assert_ne!(hash(flat), hash(&flat.replace("testing", "success")));
+    }
 
-fn alphanumericwithhypen<T>(i: T) -> IResult<T, T>
-where
-    T: InputTakeAtPosition,
-    <T as InputTakeAtPosition>::Item: AsChar,
-{
-    i.split_at_position1_complete(
-        |item| {
-            let char_item = item.as_char();
-            !(char_item == '-') && !char_item.is_alphanum()
-        },
-        ErrorKind::AlphaNumeric,
-    )
-}
+    #[test]
+    fn test_parse_config_bounded() {
+        let input = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n[passive]\nready_for_update_flag=false\n";
+        assert_eq!(parse_config_bounded(input, input.len()), parse(input));
+        assert!(parse_config_bounded(input, input.len()).is_ok());
+        assert_eq!(
+            parse_config_bounded(input, input.len() - 1),
+            Err(ConfigError::InputTooLarge)
+        );
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use libc_print::libc_println;
-    use nom::{error::Error, Err};
+    #[test]
+    fn test_parse_scalars() {
+        let input = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n[passive]\nready_for_update_flag=true\nimage_name=yy.itb\nimage_version=ver_2\nupdate_status=testing\n";
+        let mut buf = [0u8; 256];
+        buf[..input.len()].copy_from_slice(input.as_bytes());
+        let scalars = parse_scalars(core::str::from_utf8(&buf[..input.len()]).unwrap()).unwrap();
+        // the result doesn't borrow from `buf`, so it can be reused
+        buf.fill(0);
+        assert_eq!(
+            scalars,
+            ConfigScalars {
+                active_version: 1,
+                passive_flag: true,
+                passive_version: Some(2),
+                passive_status: Some(UpdateStatus::Testing),
+            }
+        );
+        assert_eq!(
+            parse_scalars("[active]\nimage_name=xx.itb\nimage_version=ver_1\n[passive]\nready_for_update_flag=false\n"),
+            Ok(ConfigScalars {
+                active_version: 1,
+                passive_flag: false,
+                passive_version: None,
+                passive_status: None,
+            })
+        );
+    }
 
     #[test]
-    fn test_config_keys() {
-        assert_eq!(config_keys("[active]"), Ok(("", ConfigKeys::Active)));
-        assert_eq!(config_keys("[passive]"), Ok(("", ConfigKeys::Passive)));
+    fn test_parse_config_located() {
+        let input = "[active]
+image_name=xx.itb
+image_version=ver_1
+
+[passive]
+ready_for_update_flag=true
+image_name=yy.itb
+  image_version=ver_2
+update_status=rebooting
+";
         assert_eq!(
-            config_keys("[passive]remaining"),
-            Ok(("remaining", ConfigKeys::Passive))
+            parse_config_located(input),
+            Err(LocatedError {
+                error: ConfigError::InvalidStatus("rebooting"),
+                line: 9,
+                column: 15
+            })
         );
         assert_eq!(
-            config_keys("prefix[passive]remaining"),
-            Err(Err::Error(Error::new(
-                "prefix[passive]remaining",
-                ErrorKind::Tag
-            )))
+            parse_config_located("[active]\nimage_name=xx.itb\n  image_version=ver_x\n"),
+            Err(LocatedError {
+                error: ConfigError::InvalidVersion("x"),
+                line: 3,
+                column: 21
+            })
         );
         assert_eq!(
-            config_keys("active"),
-            Err(Err::Error(Error::new("active", ErrorKind::Tag)))
+            parse_config_located("[active]\nimage_name=xx.itb\n").map_err(|e| (e.line, e.column)),
+            Err((3, 1))
         );
+        assert!(parse_config_located(&input.replace("rebooting", "testing")).is_ok());
+    }
+
+    #[test]
+    fn test_parse_config_reported() {
+        let input = "[active]
+            image_name=xx.itb
+            image_version=ver_1
+
+            [passive]
+            ready_for_update_flag=true
+            image_name=yy.itb
+            image_version=ver_2
+            update_status=testing
+            ";
+        let (remainder, (active, passive, report)) = parse_config_reported(input).unwrap();
+        assert_eq!((active, passive), parse_config(input).unwrap().1);
         assert_eq!(
-            config_keys("active]"),
-            Err(Err::Error(Error::new("active]", ErrorKind::Tag)))
+            report,
+            ParseReport {
+                sections: 2,
+                bytes_consumed: input.len() - remainder.len(),
+            }
+        );
+        assert_eq!(report.bytes_consumed, input.len());
+
+        // a truncated partition stops short of the whole input
+        let truncated = &input[..input.find("update_status").unwrap()];
+        let (remainder, (_, _, report)) = parse_config_reported(truncated).unwrap();
+        assert_eq!(report.sections, 2);
+        assert_eq!(report.bytes_consumed, truncated.len() - remainder.len());
+        let settings = format!("{}[settings]\nwatchdog_timeout=30\n", input);
+        assert_eq!(parse_config_reported(&settings).unwrap().1 .2.sections, 3);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_parse_config_as_verbose_error() {
+        use nom::error::{convert_error, VerboseError, VerboseErrorKind};
+
+        let input = "[active]\nimage_name=xx.itb\nimage_version=ver_x\n\
+                     [passive]\nready_for_update_flag=true\n";
+        let error = match parse_config_as::<VerboseError<&str>>(input) {
+            Err(Err::Error(e) | Err::Failure(e)) => e,
+            res => panic!("unexpected {:?}", res),
+        };
+        assert_eq!(
+            error.errors,
+            [
+                ("x", VerboseErrorKind::Nom(ErrorKind::MapRes)),
+                (&input[27..], VerboseErrorKind::Context("image_version")),
+                (input, VerboseErrorKind::Context("[active]")),
+            ]
         );
+        let trace = convert_error(input, error);
+        assert!(trace.contains("line 3"));
+        assert!(trace.contains("in image_version"));
+        assert!(trace.contains("in [active]"));
+
+        // the section is the one the failure is in, not the first one
+        let input = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n\
+                     [passive]\nready_for_update_flag=true\nupdate_status=rebooting\n";
+        let error = match parse_config_as::<VerboseError<&str>>(input) {
+            Err(Err::Failure(e)) => e,
+            res => panic!("unexpected {:?}", res),
+        };
         assert_eq!(
-            config_keys("[]"),
-            Err(Err::Error(Error::new("[]", ErrorKind::Tag)))
+            error.errors,
+            [
+                (&input[98..107], VerboseErrorKind::Nom(ErrorKind::Tag)),
+                (&input[84..], VerboseErrorKind::Context("update_status")),
+                (&input[47..], VerboseErrorKind::Context("[passive]")),
+            ]
         );
+
+        // the default error type still works the same
+        assert_eq!(parse_config_as::<Error<&str>>(input), parse_config(input));
+        let input = "[active]\nimage_name=xx.itb\nimage_version=ver_x\n";
+        assert_eq!(parse_config_as::<Error<&str>>(input), parse_config(input));
+    }
+
+    /// How many sections a `ConfigStreamParser::feed` call completed.
+    fn feed_count<'a>(
+        res: Result<CompletedSections<'a>, ConfigError<'a>>,
+    ) -> Result<usize, ConfigError<'a>> {
+        res.map(Iterator::count)
     }
 
     #[test]
-    fn test_image_name() {
+    fn test_config_stream_parser() {
+        let input = "[active]
+            image_name=signed-apertis-rpi4.itb
+            image_version=ver_1.2.3
+
+            # staged by the update daemon
+            [passive]
+            ready_for_update_flag=true
+            image_name=signed-apertis-rpi4.itb
+            image_version=ver_2
+            update_status=testing
+            ";
+        for chunk_len in [1, 7, 16, 256] {
+            let mut parser = ConfigStreamParser::<512>::new();
+            let mut completed = 0;
+            for chunk in input.as_bytes().chunks(chunk_len) {
+                for section in parser.feed(chunk).unwrap() {
+                    let Section::Active(active) = section else {
+                        panic!("unexpected section {:?}", section);
+                    };
+                    assert_eq!(active.image_name(), ("signed-apertis-rpi4", ".itb"));
+                    completed += 1;
+                }
+            }
+            // the passive section is only known to be complete once the input ends
+            assert_eq!(completed, 1);
+            assert_eq!(parser.finish(), parse_config_strict(input));
+        }
+
+        // a bad section is reported as soon as the next one starts
+        let mut parser = ConfigStreamParser::<512>::new();
         assert_eq!(
-            image_name("image_name=signed-apertis-rpi4.itbblah"),
-            Ok(("blah", ("signed-apertis-rpi4", ".itb")))
+            feed_count(parser.feed(b"[active]\nimage_name=xx.itb\n")),
+            Ok(0)
         );
         assert_eq!(
-            image_name("image_name="),
-            Err(Err::Error(Error::new("", ErrorKind::AlphaNumeric)))
+            feed_count(parser.feed(b"image_version=ver_x\n[passive]\n")),
+            Err(ConfigError::InvalidVersion("x"))
         );
+
+        let mut parser = ConfigStreamParser::<8>::new();
+        assert_eq!(feed_count(parser.feed(b"[active]")), Ok(0));
+        assert_eq!(feed_count(parser.feed(b"\n")), Err(ConfigError::BufferFull));
+
+        let mut parser = ConfigStreamParser::<8>::new();
         assert_eq!(
-            image_name("image_name=example.org:8080"),
-            Err(Err::Error(Error::new(".org:8080", ErrorKind::Tag)))
+            feed_count(parser.feed(b"[\xff]\n")),
+            Err(ConfigError::InvalidUtf8)
+        );
+
+        // several sections completed by one chunk come out together
+        let mut parser = ConfigStreamParser::<512>::new();
+        let mut sections = parser
+            .feed(
+                b"[board]\nmodel=rpi4\nhwrev=2\n[active]\nimage_name=xx.itb\n\
+                    image_version=ver_1\n[passive]\n",
+            )
+            .unwrap();
+        assert!(matches!(sections.next(), Some(Section::Board(board)) if board.model() == "rpi4"));
+        assert!(
+            matches!(sections.next(), Some(Section::Active(active)) if active.image_version() == 1)
         );
+        assert_eq!(sections.next(), None);
+    }
+
+    #[test]
+    fn test_config_stream_parser_versioned() {
+        let input = "# written by the updater\nversion=1\n\
+                     [active]\nimage_name=xx.itb\nimage_version=ver_1\n\
+                     [passive]\nready_for_update_flag=true\nimage_name=yy.itb\n\
+                     image_version=ver_2\nupdate_status=testing\n";
+        for chunk_len in [1, 5, 16, 256] {
+            let mut parser = ConfigStreamParser::<512>::new();
+            let mut completed = 0;
+            for chunk in input.as_bytes().chunks(chunk_len) {
+                completed += parser.feed(chunk).unwrap().count();
+            }
+            assert_eq!(completed, 1);
+            assert_eq!(parser.format_version(), Some(1));
+            assert_eq!(parser.finish(), parse_config_strict(input));
+        }
+
+        let mut parser = ConfigStreamParser::<512>::new();
+        assert_eq!(feed_count(parser.feed(b"version=1\n")), Ok(0));
+        assert_eq!(parser.format_version(), None);
+        assert_eq!(feed_count(parser.feed(b"[active]\n")), Ok(0));
+        assert_eq!(parser.format_version(), Some(1));
+        // a version this crate doesn't know is refused before any section is read
+        let mut parser = ConfigStreamParser::<512>::new();
         assert_eq!(
-            image_name("image_name=some-subsite.example.org:8080"),
-            Err(Err::Error(Error::new(".example.org:8080", ErrorKind::Tag)))
+            feed_count(parser.feed(b"version=2\n[active]\n")),
+            Err(ConfigError::UnsupportedFormatVersion("2"))
         );
+    }
+
+    #[test]
+    fn test_parse_config_bytes() {
+        let input: &[u8] = b"[active]
+            image_name=xx.itb
+            image_version=ver_1
+
+            [passive]
+            ready_for_update_flag=true
+            image_name=yy.itb
+            image_version=ver_2
+            update_status=testing
+            ";
+        let (rest, (active, passive)) = parse_config_bytes(input).unwrap();
+        assert_eq!(rest, b"");
+        assert_eq!(active.image_name(), ("xx", ".itb"));
+        assert_eq!(passive.update_status(), Some(UpdateStatus::Testing));
+
+        // erased flash after the config is left in the remainder
+        let mut padded = [0xff; 512];
+        padded[..input.len()].copy_from_slice(input);
+        let (rest, _) = parse_config_bytes(&padded).unwrap();
+        assert_eq!(rest, &padded[input.len()..]);
+
         assert_eq!(
-            image_name("image_name=example.123"),
-            Err(Err::Error(Error::new(".123", ErrorKind::Tag)))
+            parse_config_bytes(b"[active]\nimage_name=xx.itb\nimage_version=ver_x\n"),
+            Err(Err::Failure(Error::new(&b"x\n"[..], ErrorKind::MapRes)))
         );
     }
 
     #[test]
-    fn test_image_version() {
-        // libc_println!(
-        //     "image_version: {:?}",
-        //     image_version("image_version=ver_612634867 ")
-        // );
+    fn test_no_panic() {
+        let valid = "[settings]\nwatchdog_timeout=30\n[active]\nimage_name=xx.itb\n\
+                     image_version=ver_0x1.2.3\n[passive]\nready_for_update_flag=yes\n\
+                     image_name=yy.itb\nimage_version=ver_2 # ok\r\nupdate_status=testing\n";
+        let fragments = [
+            "[active]",
+            "[passive]",
+            "[settings]",
+            "[",
+            "]",
+            "image_name=",
+            "image_version=",
+            "ver_",
+            "0x",
+            ".",
+            ".itb",
+            "=",
+            " ",
+            "\t",
+            "\n",
+            "\r\n",
+            "\r",
+            "#",
+            "9999999999",
+            "update_status=",
+            "failed",
+            "ready_for_update_flag=",
+            "true",
+            "image_hash=",
+            "version=",
+            "[signature]",
+            "alg=",
+            "sig=",
+            "QUJD",
+            RECORD_SEPARATOR,
+            "${BOARD}",
+            "${",
+            "\"",
+            "é",
+            "\0",
+        ];
+        let check = |input: &str| {
+            let _ = parse(input).map(|config| config.validate());
+            let _ = parse_config(input);
+            let _ = parse_config_located(input);
+            let _ = parse_config_bytes(input.as_bytes());
+            let _ = parse_config_lenient(input.as_bytes());
+            let _ = parse_config_spans(input);
+            let _ = parse_config_with_raw(input);
+            let _ = parse_config_as::<Error<&str>>(input);
+            let _ = parse_many(input).count();
+            let _ = parse_signed(input);
+            let _ = signed_region(input);
+            let mut buf = [0; 512];
+            let _ = canonicalize_into(input, &mut buf);
+            let _ = section_pairs(input).count();
+            #[cfg(feature = "alloc")]
+            {
+                use nom::error::VerboseError;
+
+                if let Ok((_, signature, _)) = parse_signed(input) {
+                    let _ = signature.sig();
+                }
+                let _ = parse_config_as::<VerboseError<&str>>(input);
+                let _ = parse_config_collect_errors(input);
+                let resolver = |name: &str| (name == "BOARD").then_some("rpi4");
+                if let Ok(expanded) = expand_vars(input, resolver) {
+                    let _ = parse(&expanded);
+                }
+            }
+            let mut parser = ConfigStreamParser::<512>::new();
+            for chunk in input.as_bytes().chunks(3) {
+                if let Ok(sections) = parser.feed(chunk) {
+                    let _ = sections.count();
+                }
+            }
+            let _ = parser.finish();
+        };
+
+        // every truncation of a valid config
+        for (i, _) in valid.char_indices() {
+            check(&valid[..i]);
+            check(&valid[i..]);
+        }
+        // random sequences of grammar fragments, from a fixed-seed xorshift generator
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as usize
+        };
+        for _ in 0..2000 {
+            let mut input = alloc::string::String::new();
+            for _ in 0..next() % 24 {
+                input.push_str(fragments[next() % fragments.len()]);
+            }
+            check(&input);
+        }
+        // random bytes, including invalid UTF-8
+        for _ in 0..500 {
+            let bytes: alloc::vec::Vec<u8> = (0..next() % 64).map(|_| next() as u8).collect();
+            let _ = parse_config_bytes(&bytes);
+            let _ = parse_config_lenient(&bytes);
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_parse_config_collect_errors() {
+        let input = "[active]
+            image_name=xx.itb
+            image_version=ver_x
+
+            [passive]
+            ready_for_update_flag=true
+            image_name=yy.itb
+            image_version=ver_2
+            update_status=rebooting
+            ";
+        let (config, errors) = parse_config_collect_errors(input);
+        assert_eq!(config, None);
         assert_eq!(
-            image_version(
-                "image_version=ver_612634867
-            "
-            ),
-            Ok(("", (612634867)))
+            errors,
+            [
+                ConfigError::InvalidVersion("x"),
+                ConfigError::InvalidStatus("rebooting")
+            ]
         );
+        // the errors still point into the input
         assert_eq!(
-            image_version("image_version=ver_111.222.345"),
-            Err(Err::Error(Error::new(".222.345", ErrorKind::MultiSpace)))
+            LocatedError::new(input, errors.into_iter().nth(1).unwrap()).line,
+            9
         );
+
+        let fixed = input
+            .replace("ver_x", "ver_1")
+            .replace("rebooting", "testing");
+        let (config, errors) = parse_config_collect_errors(&fixed);
+        assert_eq!(config, Some(parse(&fixed).unwrap()));
+        assert!(errors.is_empty());
+
+        // a structural error is reported on its own
+        let (config, errors) =
+            parse_config_collect_errors("[active]\nimage_name=xx.itb\nimage_version=ver_1\n");
+        assert_eq!(config, None);
+        assert_eq!(errors, [ConfigError::Parse(Error::new("", ErrorKind::Tag))]);
     }
 
     #[test]
-    fn test_ready_for_update() {
+    fn test_get_raw_value() {
+        let section = "[passive]
+            ready_for_update_flag=true
+            image_name = xx.itb # staged
+            image_name_backup=old.itb
+            signature=sha256:abcd
+            [active]
+            signature=other";
+        assert_eq!(get_raw_value(section, "signature"), Some("sha256:abcd"));
+        assert_eq!(get_raw_value(section, "image_name"), Some("xx.itb"));
+        assert_eq!(get_raw_value(section, "image_name_backup"), Some("old.itb"));
+        // a prefix of a key isn't a match
+        assert_eq!(get_raw_value(section, "image"), None);
+        assert_eq!(get_raw_value(section, "missing"), None);
+        // the next section isn't searched
         assert_eq!(
-            ready_for_update("ready_for_update_flag=true"),
-            Ok(("", true))
+            get_raw_value("[passive]\n[active]\nsignature=x", "signature"),
+            None
         );
     }
 
     #[test]
-    fn test_update_status() {
-        // libc_println!(
-        //     "update_status: {:?}",
-        //     update_status("update_status=updating")
-        // );
+    fn test_parse_config_lenient() {
+        let text = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n\
+                    [passive]\nready_for_update_flag=false\n";
+        let expected = parse(text).unwrap();
+        let mut flash = [0xffu8; 128];
+        flash[..3].copy_from_slice(b"\xef\xbb\xbf");
+        flash[3..3 + text.len()].copy_from_slice(text.as_bytes());
+        assert_eq!(parse_config_lenient(&flash), Ok(expected.clone()));
+        // zeroed padding, after trailing whitespace, and without a BOM
+        let mut flash = [0u8; 128];
+        flash[..text.len()].copy_from_slice(text.as_bytes());
+        flash[text.len()..text.len() + 3].copy_from_slice(b"\r\n ");
+        assert_eq!(parse_config_lenient(&flash), Ok(expected));
+        assert_eq!(parse_config_lenient(b"\xff\xff"), parse(""));
         assert_eq!(
-            update_status("update_status=updating"),
-            Ok(("", UpdateStatus::Updating))
+            parse_config_lenient(b"[active]\xfe\n\xff"),
+            Err(ConfigError::InvalidUtf8)
         );
     }
 
     #[test]
-    fn test_active_conf() {
-        libc_println!(
-            "active_config: {:?}",
-            active_config(
-                "[active]
+    fn test_parse_config_spans() {
+        let input = "[active]
             image_name=xx.itb
-            image_version=ver_123 "
-            )
+            image_version=ver_1
+
+            [passive]
+            ready_for_update_flag=true
+            image_name = yy.itb   # staged
+            image_version=ver_2
+            update_status=testing
+            ";
+        let (_, passive, spans) = parse_config_spans(input).unwrap();
+        assert_eq!(&input[spans.active_image_name.clone()], "xx.itb");
+        assert_eq!(&input[spans.ready_for_update_flag.clone()], "true");
+        assert_eq!(&input[spans.passive_image_name.clone().unwrap()], "yy.itb");
+        assert_eq!(passive.update_status(), Some(UpdateStatus::Testing));
+        assert_eq!(spans.boot_attempts, None);
+
+        // patch `update_status` in place
+        let span = spans.update_status.unwrap();
+        assert_eq!(&input[span.clone()], "testing");
+        let mut buf = [0u8; 512];
+        let buf = &mut buf[..input.len()];
+        buf.copy_from_slice(input.as_bytes());
+        buf[span].copy_from_slice(b"success");
+        let (_, passive) = parse_config_strict(core::str::from_utf8(buf).unwrap()).unwrap();
+        assert_eq!(passive.update_status(), Some(UpdateStatus::Success));
+    }
+
+    #[cfg(feature = "multi-slot")]
+    #[test]
+    fn test_parse_config_spans_multi_slot() {
+        let input = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n\
+                     [passive]\nready_for_update_flag=true\nimage_name=yy.itb\n\
+                     image_version=ver_2\nupdate_status=updating\n\
+                     [passive]\nready_for_update_flag=false\nimage_name=zz.itb\n\
+                     image_version=ver_3\nupdate_status=success\n";
+        let (_, passive, spans) = parse_config_spans(input).unwrap();
+        assert_eq!(passive.update_status(), Some(UpdateStatus::Updating));
+        assert_eq!(&input[spans.update_status.unwrap()], "updating");
+        assert!(passive.ready_for_update_flag());
+        assert_eq!(&input[spans.ready_for_update_flag], "true");
+        assert_eq!(&input[spans.passive_image_name.unwrap()], "yy.itb");
+        assert_eq!(&input[spans.passive_image_version.unwrap()], "ver_2");
+    }
+
+    #[test]
+    fn test_parse_config_with_raw() {
+        let active = "[active]\nimage_name = xx.itb   # signed\nimage_version=ver_1\n";
+        let passive = "[passive]\nready_for_update_flag=true\nimage_name=yy.itb\n\
+                       image_version=ver_2\nupdate_status=testing\n";
+        let settings = "[settings]\nwatchdog_timeout=30\n";
+        let input = format!("# staged\n{}\n{}\n  {}", passive, active, settings);
+        let (config, raw) = parse_config_with_raw(&input).unwrap();
+        assert_eq!(config, parse(&input).unwrap());
+        assert_eq!(
+            raw,
+            RawSections {
+                board: None,
+                active,
+                passive,
+                recovery: None,
+                settings: Some(settings),
+            }
         );
+        // the slices point into the input
         assert_eq!(
-            active_config(
-                "[active]
-            image_name=xx.itb
-            image_version=ver_123 "
-            ),
-            Ok((
-                "",
-                ActiveConf {
-                    active_config: ConfigKeys::Active,
-                    image_name: ("xx", ".itb"),
-                    image_version: 123
-                }
-            ))
+            &input[offset_in(&input, raw.active)..][..active.len()],
+            active
+        );
+
+        assert_eq!(
+            parse_config_with_raw("[active]\nimage_name=xx.itb\n"),
+            Err(ConfigError::MissingField(IMAGE_VERSION_KEY))
         );
     }
 
     #[test]
-    fn test_passive_conf() {
+    fn test_parse_sections() {
+        let active = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n";
+        let passive = "[passive]\nready_for_update_flag=true\nimage_name=yy.itb\n\
+                       image_version=ver_2\nupdate_status=testing\n";
+        let joined = format!("{}{}", active, passive);
+        let (_, combined) = parse_config(&joined).unwrap();
+        assert_eq!(parse_active(active), Ok(("", combined.0)));
+        assert_eq!(parse_passive(passive), Ok(("", combined.1)));
         assert_eq!(
-            passive_config(
-                "
-                [passive]
-                ready_for_update_flag=true
-                image_name=xx.itb
-                image_version=ver_123
-                update_status=updating "
-            ),
-            Ok((
-                "",
-                PassiveConf {
-                    passive_config: ConfigKeys::Passive,
-                    ready_for_update_flag: true,
-                    image_name: Some(("xx", ".itb")),
-                    image_version: Some(123),
-                    update_status: Some(UpdateStatus::Updating)
-                }
-            ))
+            parse_active("[passive]\n"),
+            Err(Err::Error(Error::new("[passive]\n", ErrorKind::Verify)))
+        );
+    }
+
+    #[test]
+    fn test_parse_one_slot_only() {
+        let input = "[passive]\nready_for_update_flag=true\nimage_name=yy.itb\n\
+                     image_version=ver_2\nupdate_status=testing\n\
+                     [active]\nimage_name=xx.itb\nimage_version=ver_1\n";
+        let config = parse(input).unwrap();
+        assert_eq!(parse_active_only(input), Ok(config.active));
+        assert_eq!(parse_passive_only(input), Ok(config.passive));
+        // the other section still has to be there and valid
+        let active = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n";
+        assert!(parse_active_only(active).is_err());
+        assert_eq!(
+            parse_passive_only(&input.replace("ver_1", "ver_x")),
+            Err(ConfigError::InvalidVersion("x"))
         );
+    }
+
+    #[test]
+    fn test_parse_many() {
+        let first = "[active]\nimage_name=xx.itb\nimage_version=ver_1\n\
+                     [passive]\nready_for_update_flag=false\n";
+        let second = "[active]\nimage_name=yy.itb\nimage_version=ver_2\n\
+                      [passive]\nready_for_update_flag=true\nimage_name=zz.itb\n\
+                      image_version=ver_3\nupdate_status=testing\n";
+        let input = format!("{}\n---\n{}\n---\n", first, second);
+        let mut configs = parse_many(&input);
+        assert_eq!(configs.next(), Some(parse(first)));
+        assert_eq!(configs.next(), Some(parse(second)));
+        assert_eq!(configs.next(), None);
+        assert_eq!(parse_many("").count(), 0);
+
+        // a bad record is reported without losing the next one
+        let input = format!("{}---\n[active]\nimage_name=xx.itb\n---\n{}", first, second);
+        let mut configs = parse_many(&input);
+        assert!(configs.next().unwrap().is_ok());
+        assert!(configs.next().unwrap().is_err());
+        assert_eq!(configs.next(), Some(parse(second)));
+        assert_eq!(configs.next(), None);
+    }
+
+    #[test]
+    fn test_parse_config_with() {
+        let names = SectionNames {
+            active: "slot_a",
+            passive: "slot_b",
+            ..SectionNames::DEFAULT
+        };
+        let renamed = "[slot_a]
+            image_name=xx.itb
+            image_version=ver_1
+
+            [slot_b]
+            ready_for_update_flag=true
+            image_name=yy.itb
+            image_version=ver_2
+            update_status=testing";
+        let standard = renamed
+            .replace("slot_a", "active")
+            .replace("slot_b", "passive");
+        let (rest, (active, passive)) = parse_config_with(renamed, &names).unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(Ok(("", (active, passive))), parse_config(&standard));
+        // the standard names are no longer accepted
         assert_eq!(
-            passive_config(
-                "
-                [passive]
-                ready_for_update_flag=false
-                image_name=
-                image_version=none
-                update_status=none "
-            ),
-            Ok((
-                "image_name=
-                image_version=none
-                update_status=none ",
-                PassiveConf {
-                    passive_config: ConfigKeys::Passive,
-                    ready_for_update_flag: false,
-                    image_name: None,
-                    image_version: None,
-                    update_status: None
-                }
-            ))
+            parse_config_with(&standard, &names),
+            Err(Err::Error(Error::new(&standard[..8], ErrorKind::Tag)))
         );
     }
 
@@ -406,14 +8027,30 @@ mod tests {
                     ActiveConf {
                         active_config: ConfigKeys::Active,
                         image_name: ("xx", ".itb"),
-                        image_version: 34488734
+                        image_dir: None,
+                        image_version: 34488734,
+                        image_semver: None,
+                        image_hash: None,
+                        channel: None,
+                        min_hwrev: None,
                     },
                     PassiveConf {
                         passive_config: ConfigKeys::Passive,
                         ready_for_update_flag: true,
                         image_name: Some(("xx", ".itb")),
+                        image_dir: None,
                         image_version: Some(34488735),
-                        update_status: Some(UpdateStatus::Updating)
+                        image_semver: None,
+                        image_hash: None,
+                        channel: None,
+                        min_hwrev: None,
+                        update_status: Some(UpdateStatus::Updating),
+                        boot_attempts: None,
+                        test_window_secs: None,
+                        dry_run: None,
+                        staged_at: None,
+                        retry_backoff: None,
+                        unknown_fields: UnknownFields::EMPTY,
                     }
                 )
             ))
@@ -432,21 +8069,35 @@ mod tests {
                 update_status=updating"
             ),
             Ok((
-                "image_name=
-                image_version=ver_34488735
-                update_status=updating",
+                "",
                 (
                     ActiveConf {
                         active_config: ConfigKeys::Active,
                         image_name: ("xx", ".itb"),
-                        image_version: 34488734
+                        image_dir: None,
+                        image_version: 34488734,
+                        image_semver: None,
+                        image_hash: None,
+                        channel: None,
+                        min_hwrev: None,
                     },
                     PassiveConf {
                         passive_config: ConfigKeys::Passive,
                         ready_for_update_flag: false,
                         image_name: None,
+                        image_dir: None,
                         image_version: None,
-                        update_status: None
+                        image_semver: None,
+                        image_hash: None,
+                        channel: None,
+                        min_hwrev: None,
+                        update_status: None,
+                        boot_attempts: None,
+                        test_window_secs: None,
+                        dry_run: None,
+                        staged_at: None,
+                        retry_backoff: None,
+                        unknown_fields: UnknownFields::EMPTY,
                     }
                 )
             ))
@@ -469,14 +8120,30 @@ mod tests {
                     ActiveConf {
                         active_config: ConfigKeys::Active,
                         image_name: ("xx", ".itb"),
-                        image_version: 34488734
+                        image_dir: None,
+                        image_version: 34488734,
+                        image_semver: None,
+                        image_hash: None,
+                        channel: None,
+                        min_hwrev: None,
                     },
                     PassiveConf {
                         passive_config: ConfigKeys::Passive,
                         ready_for_update_flag: false,
                         image_name: None,
+                        image_dir: None,
                         image_version: None,
-                        update_status: None
+                        image_semver: None,
+                        image_hash: None,
+                        channel: None,
+                        min_hwrev: None,
+                        update_status: None,
+                        boot_attempts: None,
+                        test_window_secs: None,
+                        dry_run: None,
+                        staged_at: None,
+                        retry_backoff: None,
+                        unknown_fields: UnknownFields::EMPTY,
                     }
                 )
             ))